@@ -0,0 +1,182 @@
+use serde::{Serialize, Deserialize};
+
+use crate::client::{WsdClient, WSDResult, WSDStreamResult};
+use crate::error::WsdError;
+use crate::types::PlotParameters;
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Represent options of rendering many diagrams at once.
+///
+/// Requests of all workers go through the rate limiter of the client
+/// (`RateLimiter::global()` by default), so rate limit is set there
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchOptions {
+    /// Maximum number of diagrams rendered at the same time. 0 is treated as 1
+    pub concurrency: usize,
+}
+
+impl Default for BatchOptions {
+    fn default() -> BatchOptions {
+        BatchOptions {
+            concurrency: 4,
+        }
+    }
+}
+
+/// Represent result of one job of the batch
+pub struct BatchResult<I, R = WSDResult> {
+    /// Id of the job as it was given to `WsdClient::render_batch`
+    pub id: I,
+
+    pub result: Result<R, WsdError>,
+}
+
+// Job with its position in the batch
+type IndexedJob<I, T> = (usize, I, T);
+
+impl WsdClient {
+    /// plot many diagrams using several concurrent workers.
+    ///
+    /// Each job is `(id, spec, parameters)`. Id is only used to match results with jobs.
+    /// Failure of one job does not stop others: each job gets its own result.
+    /// Results are returned in the order of jobs.
+    /// Every job is rendered with `get_diagram`, so cache and rate limiter of the client are used.
+    pub fn render_batch<I, S, J>(&self, jobs: J, options: &BatchOptions) -> Vec<BatchResult<I>>
+        where
+            I: Send + 'static,
+            S: Into<String>,
+            J: IntoIterator<Item=(I, S, PlotParameters)>,
+    {
+        let jobs = jobs
+            .into_iter()
+            .map(|(id, spec, parameters)| (id, (spec.into(), parameters)));
+        self.run_batch(jobs, options, |client, (spec, parameters): (String, PlotParameters)| {
+            client.get_diagram(&spec, &parameters)
+        })
+    }
+
+    /// plot many diagrams writing each of them into its own writer (e.g. `BufWriter<File>`)
+    /// without holding diagrams in memory.
+    ///
+    /// Each job is `(id, spec, parameters, writer)`. It is the same as `render_batch`,
+    /// but every job is rendered with `render_to_writer`.
+    pub fn render_batch_to_writers<I, S, W, J>(&self, jobs: J, options: &BatchOptions) -> Vec<BatchResult<I, WSDStreamResult>>
+        where
+            I: Send + 'static,
+            S: Into<String>,
+            W: Write + Send + 'static,
+            J: IntoIterator<Item=(I, S, PlotParameters, W)>,
+    {
+        let jobs = jobs
+            .into_iter()
+            .map(|(id, spec, parameters, writer)| (id, (spec.into(), parameters, writer)));
+        self.run_batch(jobs, options, |client, (spec, parameters, mut writer): (String, PlotParameters, W)| {
+            client.render_to_writer(&spec, &parameters, &mut writer)
+        })
+    }
+
+    // Renders jobs with `render` in worker threads. Results are in the order of jobs
+    fn run_batch<I, T, R, J, F>(&self, jobs: J, options: &BatchOptions, render: F) -> Vec<BatchResult<I, R>>
+        where
+            I: Send + 'static,
+            T: Send + 'static,
+            R: Send + 'static,
+            J: IntoIterator<Item=(I, T)>,
+            F: Fn(&WsdClient, T) -> Result<R, WsdError> + Send + Sync + 'static,
+    {
+        let queue: VecDeque<IndexedJob<I, T>> = jobs
+            .into_iter()
+            .enumerate()
+            .map(|(index, (id, job))| (index, id, job))
+            .collect();
+        let jobs_count = queue.len();
+        let queue = Arc::new(Mutex::new(queue));
+        let render = Arc::new(render);
+
+        let (sender, receiver) = channel();
+        let workers = (0..options.concurrency.max(1).min(jobs_count))
+            .map(|_| {
+                let client = self.clone();
+                let queue = queue.clone();
+                let render = render.clone();
+                let sender = sender.clone();
+                thread::spawn(move || loop {
+                    let job = queue.lock().expect("batch queue lock").pop_front();
+                    let (index, id, job) = match job {
+                        Some(job) => job,
+                        None => break,
+                    };
+                    let result = render(&client, job);
+                    if sender.send((index, BatchResult { id, result })).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(sender);
+
+        let mut results = (0..jobs_count).map(|_| None).collect::<Vec<Option<BatchResult<I, R>>>>();
+        for (index, result) in receiver {
+            results[index] = Some(result);
+        }
+        for worker in workers {
+            if let Err(panic) = worker.join() {
+                std::panic::resume_unwind(panic);
+            }
+        }
+        results
+            .into_iter()
+            .map(|result| result.expect("every job of the batch has result"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batch::BatchOptions;
+    use crate::cache::{CacheOptions, CachedDiagram, DiskCache};
+    use crate::client::WsdClient;
+    use crate::retry::RetryPolicy;
+    use crate::types::{Format, PlotParameters};
+
+    #[test]
+    fn render_batch_test() {
+        let dir = std::env::temp_dir().join(format!("wsdclient-batch-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let parameters = PlotParameters::default();
+        let cache = DiskCache::new(CacheOptions::new(&dir)).unwrap();
+        cache.put(&DiskCache::key("A->B: cached", &parameters), &CachedDiagram {
+            diagram: b"PNG DATA".to_vec(),
+            actual_format: Format::Png,
+            errors: vec![],
+            response: serde_json::from_str(r#"{"img": "?png=mscKTO107"}"#).unwrap(),
+        }).unwrap();
+
+        // Nothing listens on this port, so jobs not in cache fail
+        let client = WsdClient::builder()
+            .base_url("http://127.0.0.1:1")
+            .retry_policy(RetryPolicy::none())
+            .cache(CacheOptions::new(&dir))
+            .build()
+            .unwrap();
+        let jobs = vec![
+            (1, "A->B: not cached", parameters.clone()),
+            (2, "A->B: cached", parameters.clone()),
+            (3, "A->B: not cached either", parameters.clone()),
+        ];
+        let results = client.render_batch(jobs, &BatchOptions { concurrency: 2 });
+
+        assert_eq!(results.iter().map(|result| result.id).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert!(results[0].result.is_err());
+        let cached = results[1].result.as_ref().unwrap();
+        assert!(cached.from_cache);
+        assert_eq!(cached.diagram, b"PNG DATA".to_vec());
+        assert!(results[2].result.is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,497 @@
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+use crate::client::WebSequenceDiagramResponse;
+use crate::error::WsdError;
+use crate::types::{DiagramError, Format, PlotParameters, WSDEnum};
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Represent options of on-disk cache of rendered diagrams
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheOptions {
+    /// Directory where diagrams are stored
+    pub directory: PathBuf,
+
+    /// Maximum total size of cached diagrams in bytes. Least recently used diagrams are removed
+    /// when it is exceeded. None means unlimited
+    pub max_size: Option<u64>,
+
+    /// How long cached diagram is valid. None means forever
+    pub ttl: Option<Duration>,
+}
+
+impl CacheOptions {
+    /// Options with default limits: 100 MiB and 30 days
+    pub fn new<P: Into<PathBuf>>(directory: P) -> CacheOptions {
+        CacheOptions {
+            directory: directory.into(),
+            max_size: Some(100 * 1024 * 1024),
+            ttl: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+        }
+    }
+
+    /// Options with default per-user cache directory. E.g. `~/.cache/wsdclient` on Linux.
+    /// None if the directory cannot be determined
+    pub fn user_default() -> Option<CacheOptions> {
+        dirs::cache_dir().map(|dir| CacheOptions::new(dir.join("wsdclient")))
+    }
+}
+
+/// Represent diagram stored in the cache
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedDiagram {
+    /// Content of the diagram
+    pub diagram: Vec<u8>,
+
+    /// Actual format of the diagram
+    pub actual_format: Format,
+
+    /// Errors in the diagram
+    pub errors: Vec<DiagramError>,
+
+    /// Response of API on the request for diagram
+    pub response: WebSequenceDiagramResponse,
+}
+
+// Information about cached diagram stored near the diagram
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    actual_format: Format,
+    errors: Vec<DiagramError>,
+    response: WebSequenceDiagramResponse,
+    // Seconds since UNIX epoch
+    created: u64,
+    last_access: u64,
+}
+
+/// Content-addressed on-disk cache of rendered diagrams.
+///
+/// Key is a hash of normalized diagram specification and plot parameters (style, format, paper size,
+/// paper orientation and scale). Each diagram is stored as two files: `<key>.diagram` with content
+/// and `<key>.json` with actual format, errors and API response.
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    options: CacheOptions,
+}
+
+impl DiskCache {
+    /// Open cache. Directory is created if it does not exist
+    pub fn new(options: CacheOptions) -> Result<DiskCache, WsdError> {
+        fs::create_dir_all(&options.directory)
+            .map_err(|err| cache_error(&options.directory, err))?;
+        Ok(DiskCache { options })
+    }
+
+    pub fn options(&self) -> &CacheOptions {
+        &self.options
+    }
+
+    /// Key of the diagram in the cache. API key is not part of the key
+    pub fn key(spec: &str, parameters: &PlotParameters) -> String {
+        let mut hasher = Sha256::new();
+        hasher.input(normalize_spec(spec).as_bytes());
+        let fields = [
+            parameters.style.wsd_value(),
+            parameters.format.wsd_value(),
+            parameters.paper_size.as_ref().map(WSDEnum::wsd_value).unwrap_or_default(),
+            parameters.paper_orientation.as_ref().map(WSDEnum::wsd_value).unwrap_or_default(),
+            parameters.scale.map(|scale| scale.to_string()).unwrap_or_default(),
+        ];
+        for field in &fields {
+            // Separator prevents collisions between different splits of the same text
+            hasher.input(b"\0");
+            hasher.input(field.as_bytes());
+        }
+        format!("{:x}", hasher.result())
+    }
+
+    /// Get diagram from the cache. Expired or broken entries are removed
+    pub fn get(&self, key: &str) -> Option<CachedDiagram> {
+        let mut entry = self.read_entry(key)?;
+        if self.is_expired(&entry) {
+            self.remove(key);
+            return None;
+        }
+        let mut diagram = vec![];
+        if File::open(self.diagram_path(key))
+            .and_then(|mut f| f.read_to_end(&mut diagram))
+            .is_err() {
+            self.remove(key);
+            return None;
+        }
+        entry.last_access = now();
+        // Failure to update access time only affects eviction order
+        let _ = self.write_entry(key, &entry);
+        Some(CachedDiagram {
+            diagram,
+            actual_format: entry.actual_format,
+            errors: entry.errors,
+            response: entry.response,
+        })
+    }
+
+    /// Store diagram in the cache
+    pub fn put(&self, key: &str, diagram: &CachedDiagram) -> Result<(), WsdError> {
+        let mut writer = self.writer()?;
+        writer.write_all(&diagram.diagram)
+            .map_err(|err| cache_error(&writer.path, err))?;
+        writer.commit(key, diagram.actual_format.clone(), diagram.errors.clone(), diagram.response.clone())
+    }
+
+    /// Start storing diagram in the cache. Content is written into temporary file
+    /// and becomes visible only after `CacheWriter::commit`
+    pub fn writer(&self) -> Result<CacheWriter, WsdError> {
+        let path = self.options.directory.join(format!(
+            ".tmp-{}-{}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let file = File::create(&path)
+            .map_err(|err| cache_error(&path, err))?;
+        Ok(CacheWriter {
+            cache: self.clone(),
+            file: Some(file),
+            path,
+            committed: false,
+        })
+    }
+
+    /// Remove diagram from the cache
+    pub fn remove(&self, key: &str) {
+        let _ = fs::remove_file(self.entry_path(key));
+        let _ = fs::remove_file(self.diagram_path(key));
+    }
+
+    fn is_expired(&self, entry: &CacheEntry) -> bool {
+        match self.options.ttl {
+            Some(ttl) => entry.created.saturating_add(ttl.as_secs()) < now(),
+            None => false,
+        }
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.options.directory.join(format!("{}.json", key))
+    }
+
+    fn diagram_path(&self, key: &str) -> PathBuf {
+        self.options.directory.join(format!("{}.diagram", key))
+    }
+
+    fn read_entry(&self, key: &str) -> Option<CacheEntry> {
+        let f = File::open(self.entry_path(key)).ok()?;
+        serde_json::from_reader(f).ok()
+    }
+
+    fn write_entry(&self, key: &str, entry: &CacheEntry) -> Result<(), WsdError> {
+        let path = self.entry_path(key);
+        let tmp_path = path.with_extension("json.tmp");
+        let data = serde_json::to_vec(entry)
+            .map_err(|err| cache_error(&path, io::Error::new(io::ErrorKind::Other, err)))?;
+        fs::write(&tmp_path, data)
+            .and_then(|_| fs::rename(&tmp_path, &path))
+            .map_err(|err| {
+                let _ = fs::remove_file(&tmp_path);
+                cache_error(&path, err)
+            })
+    }
+
+    // Removes expired and least recently used entries until total size fits into max_size.
+    // Temporary files left by interrupted writers are removed too
+    fn evict(&self) -> Result<(), WsdError> {
+        self.remove_stale_temp_files(STALE_TEMP_FILE_AGE);
+        let dir = fs::read_dir(&self.options.directory)
+            .map_err(|err| cache_error(&self.options.directory, err))?;
+        let mut entries = vec![];
+        for dir_entry in dir.filter_map(Result::ok) {
+            let path = dir_entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let key = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(key) => key.to_owned(),
+                None => continue,
+            };
+            match self.read_entry(&key) {
+                Some(ref entry) if !self.is_expired(entry) => {
+                    let size = file_size(&path) + file_size(&self.diagram_path(&key));
+                    entries.push((entry.last_access, size, key));
+                }
+                _ => self.remove(&key),
+            }
+        }
+
+        if let Some(max_size) = self.options.max_size {
+            // Oldest access first
+            entries.sort();
+            let mut total_size: u64 = entries.iter().map(|(_, size, _)| size).sum();
+            for (_, size, key) in entries {
+                if total_size <= max_size {
+                    break;
+                }
+                self.remove(&key);
+                total_size -= size;
+            }
+        }
+        Ok(())
+    }
+
+    // Removes temporary files of writers and entries which are not modified for `max_age`.
+    // Younger files may be written by other processes right now
+    fn remove_stale_temp_files(&self, max_age: Duration) {
+        let dir = match fs::read_dir(&self.options.directory) {
+            Ok(dir) => dir,
+            Err(_) => return,
+        };
+        for dir_entry in dir.filter_map(Result::ok) {
+            let is_temp = match dir_entry.file_name().to_str() {
+                Some(name) => name.starts_with(".tmp-") || name.ends_with(".json.tmp"),
+                None => false,
+            };
+            let is_stale = dir_entry.metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .map_or(false, |age| age >= max_age);
+            if is_temp && is_stale {
+                let _ = fs::remove_file(dir_entry.path());
+            }
+        }
+    }
+}
+
+// Temporary files which are not modified for this time are left by interrupted writers
+const STALE_TEMP_FILE_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Writer of the diagram into the cache. Obtained from `DiskCache::writer`.
+/// If it is dropped without commit or commit fails, written data is discarded
+pub struct CacheWriter {
+    cache: DiskCache,
+    file: Option<File>,
+    path: PathBuf,
+    // Temporary file is renamed into the cache, so it is not removed on drop
+    committed: bool,
+}
+
+impl CacheWriter {
+    /// Make written diagram available in the cache under `key`
+    pub fn commit(mut self, key: &str, actual_format: Format, errors: Vec<DiagramError>, response: WebSequenceDiagramResponse)
+        -> Result<(), WsdError> {
+        if let Some(mut file) = self.file.take() {
+            file.flush().map_err(|err| cache_error(&self.path, err))?;
+        }
+        let diagram_path = self.cache.diagram_path(key);
+        fs::rename(&self.path, &diagram_path)
+            .map_err(|err| cache_error(&diagram_path, err))?;
+        self.committed = true;
+        let now = now();
+        self.cache.write_entry(key, &CacheEntry {
+            actual_format,
+            errors,
+            response,
+            created: now,
+            last_access: now,
+        }).map_err(|err| {
+            // Diagram without entry is never found and never evicted
+            let _ = fs::remove_file(&diagram_path);
+            err
+        })?;
+        self.cache.evict()
+    }
+}
+
+impl Write for CacheWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.file {
+            Some(ref mut file) => file.write(buf),
+            None => Err(io::Error::new(io::ErrorKind::Other, "cache writer is already committed")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file {
+            Some(ref mut file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        // File is closed before removal
+        self.file.take();
+        if !self.committed {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+// Line endings and trailing whitespace do not change the diagram.
+// Leading empty lines are kept, because they change line numbers in errors
+fn normalize_spec(spec: &str) -> String {
+    let lines: Vec<&str> = spec
+        .lines()
+        .map(|line| line.trim_end())
+        .collect();
+    let len = lines.iter().rposition(|line| !line.is_empty()).map_or(0, |pos| pos + 1);
+    lines[..len].join("\n")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0)
+}
+
+fn cache_error(path: &Path, err: io::Error) -> WsdError {
+    WsdError::Cache {
+        path: path.to_owned(),
+        source: err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::{CacheOptions, CachedDiagram, DiskCache};
+    use crate::client::WebSequenceDiagramResponse;
+    use crate::types::{Format, PlotParameters, Style};
+
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wsdclient-cache-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn cached_diagram(diagram: &[u8]) -> CachedDiagram {
+        CachedDiagram {
+            diagram: diagram.to_vec(),
+            actual_format: Format::Png,
+            errors: vec![],
+            response: serde_json::from_str(r#"{"img": "?png=mscKTO107", "errors": []}"#).unwrap(),
+        }
+    }
+
+    #[test]
+    fn key_test() {
+        let parameters = PlotParameters::default();
+        let key = DiskCache::key("A->B: text\n", &parameters);
+        assert_eq!(key, DiskCache::key("A->B: text  \r\n\r\n", &parameters));
+        assert_ne!(key, DiskCache::key("\nA->B: text", &parameters));
+        assert_ne!(key, DiskCache::key("A->B: text", &PlotParameters { style: Style::Napkin, ..PlotParameters::default() }));
+        assert_eq!(key, DiskCache::key("A->B: text", &PlotParameters { api_key: Some("key".into()), ..PlotParameters::default() }));
+    }
+
+    #[test]
+    fn put_get_test() {
+        let dir = temp_dir("put-get");
+        let cache = DiskCache::new(CacheOptions::new(&dir)).unwrap();
+        assert!(cache.get("key").is_none());
+        cache.put("key", &cached_diagram(b"PNG DATA")).unwrap();
+        assert_eq!(cache.get("key"), Some(cached_diagram(b"PNG DATA")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn ttl_test() {
+        let dir = temp_dir("ttl");
+        let cache = DiskCache::new(CacheOptions {
+            ttl: Some(Duration::from_secs(0)),
+            ..CacheOptions::new(&dir)
+        }).unwrap();
+        cache.put("key", &cached_diagram(b"PNG DATA")).unwrap();
+        // Entry created in the same second is still valid with zero ttl, so make it older
+        let mut entry = cache.read_entry("key").unwrap();
+        entry.created -= 10;
+        cache.write_entry("key", &entry).unwrap();
+        assert!(cache.get("key").is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn lru_eviction_test() {
+        let dir = temp_dir("lru");
+        let cache = DiskCache::new(CacheOptions {
+            max_size: Some(2500),
+            ..CacheOptions::new(&dir)
+        }).unwrap();
+        cache.put("first", &cached_diagram(&[1u8; 1000])).unwrap();
+        cache.put("second", &cached_diagram(&[2u8; 1000])).unwrap();
+        // Make `first` recently used
+        let mut entry = cache.read_entry("second").unwrap();
+        entry.last_access -= 10;
+        cache.write_entry("second", &entry).unwrap();
+        assert!(cache.get("first").is_some());
+
+        cache.put("third", &cached_diagram(&[3u8; 1000])).unwrap();
+        assert!(cache.get("second").is_none());
+        assert!(cache.get("first").is_some());
+        assert!(cache.get("third").is_some());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn uncommitted_writer_test() {
+        let dir = temp_dir("uncommitted");
+        let cache = DiskCache::new(CacheOptions::new(&dir)).unwrap();
+        {
+            use std::io::Write;
+            let mut writer = cache.writer().unwrap();
+            writer.write_all(b"PARTIAL").unwrap();
+        }
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn failed_commit_test() {
+        let dir = temp_dir("failed-commit");
+        let cache = DiskCache::new(CacheOptions::new(&dir)).unwrap();
+        let diagram = cached_diagram(b"PNG DATA");
+        // Renaming into missing subdirectory fails
+        assert!(cache.put("missing/key", &diagram).is_err());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stale_temp_files_test() {
+        let dir = temp_dir("stale");
+        let cache = DiskCache::new(CacheOptions::new(&dir)).unwrap();
+        std::fs::write(dir.join(".tmp-1-2"), b"PARTIAL").unwrap();
+        std::fs::write(dir.join("key.json.tmp"), b"{").unwrap();
+        // Recent temporary files may belong to running writers
+        cache.put("key", &cached_diagram(b"PNG DATA")).unwrap();
+        assert!(dir.join(".tmp-1-2").exists());
+
+        cache.remove_stale_temp_files(Duration::from_secs(0));
+        assert!(!dir.join(".tmp-1-2").exists());
+        assert!(!dir.join("key.json.tmp").exists());
+        assert!(cache.get("key").is_some());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn response_preserved_test() {
+        // response is stored as is, including unknown fields
+        let dir = temp_dir("response");
+        let cache = DiskCache::new(CacheOptions::new(&dir)).unwrap();
+        let mut diagram = cached_diagram(b"PNG DATA");
+        diagram.response = serde_json::from_str::<WebSequenceDiagramResponse>(
+            r#"{"img": "?png=mscKTO107", "errors": [], "remaining": 3}"#
+        ).unwrap();
+        cache.put("key", &diagram).unwrap();
+        assert_eq!(cache.get("key").unwrap().response, diagram.response);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
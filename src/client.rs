@@ -2,16 +2,51 @@ use regex::Regex;
 use serde::{Serialize, Deserialize};
 use crate::types::{PlotParameters, DiagramError, Format};
 
-use std::error::Error;
+use futures::{future, Future};
+use reqwest::r#async::Client as AsyncClient;
+use reqwest::StatusCode;
+
+use crate::cache::{CacheOptions, CacheWriter, CachedDiagram, DiskCache};
+use crate::connection::{ConnectionOptions, Scheme};
+use crate::error::WsdError;
+use crate::fixtures::{FixtureTransport, Fixtures};
+use crate::rate_limit::{parse_retry_after, RateLimiter};
+use crate::retry::{retry, retry_async, RetryPolicy};
+use crate::secret::Secret;
+use crate::transport::{redacted_form, ReqwestTransport, Transport, TransportResponse};
 use crate::types::WSDEnum;
 
-// Represent response from websequence diagram website
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-struct WebSequenceDiagramResponse {
-    img: String,
-    errors: Vec<String>,
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Represent response from websequencediagrams API on the request for diagram
+///
+/// Example of response:
+///
+/// `{"img": "?png=mscKTO107", "page": 0, "numPages": 1, "errors": []}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebSequenceDiagramResponse {
+    /// url of the diagram relative to API address. E.g. "?png=mscKTO107"
+    pub img: String,
+
+    /// errors in the diagram as returned by API. E.g. "Line 1: Syntax error."
+    #[serde(default)]
+    pub errors: Vec<String>,
 
-    // TODO(mkl): add aditional fields
+    /// page of the diagram
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+
+    /// number of pages in the diagram
+    #[serde(rename = "numPages", default, skip_serializing_if = "Option::is_none")]
+    pub num_pages: Option<u32>,
+
+    /// all other fields returned by API (e.g. quota or warnings). They are preserved as is,
+    /// so new fields are not lost
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
 }
 
 
@@ -30,11 +65,801 @@ pub struct WSDResult {
     /// but no api key are provided.
     /// Format is determined from returned url
     /// "?png=mscKTO107" for png
-    pub actual_format: Format
+    pub actual_format: Format,
+
+    /// Number of attempts made to send diagram specification (first request)
+    pub message_attempts: u32,
+
+    /// Number of attempts made to download the diagram (second request)
+    pub image_attempts: u32,
+
+    /// Full response of API on the request for diagram
+    pub response: WebSequenceDiagramResponse,
+
+    /// Diagram was taken from on-disk cache. No requests were made
+    pub from_cache: bool,
+}
+
+/// Contains result of plotting diagram into writer (see `WsdClient::render_to_writer`).
+/// It is the same as `WSDResult` but content of the diagram is already written
+pub struct WSDStreamResult {
+    /// Number of bytes of the diagram written
+    pub bytes_written: u64,
+
+    /// Vector with errors. Errors are not fatal
+    pub errors: Vec<DiagramError>,
+
+    /// Actual format of the diagram. It may be different from requested
+    pub actual_format: Format,
+
+    /// Number of attempts made to send diagram specification (first request)
+    pub message_attempts: u32,
+
+    /// Number of attempts made to download the diagram (second request)
+    pub image_attempts: u32,
+
+    /// Full response of API on the request for diagram
+    pub response: WebSequenceDiagramResponse,
+
+    /// Diagram was taken from on-disk cache. No requests were made
+    pub from_cache: bool,
+}
+
+/// Address of the public websequencediagrams API
+pub const DEFAULT_BASE_URL: &str = "http://www.websequencediagrams.com";
+
+/// Client for websequencediagrams API.
+///
+/// Client owns connection pool, so it is cheaper to create it once and reuse it for
+/// many diagrams. Cloning the client is cheap, clones share the same pool.
+#[derive(Debug, Clone)]
+pub struct WsdClient {
+    transport: Arc<dyn Transport>,
+    base_url: String,
+    default_parameters: PlotParameters,
+    retry_policy: RetryPolicy,
+    cache: Option<DiskCache>,
+    rate_limiter: Arc<RateLimiter>,
+    fixtures: Option<Fixtures>,
+}
+
+/// Builder for `WsdClient`
+#[derive(Debug, Clone)]
+pub struct WsdClientBuilder {
+    base_url: String,
+    default_parameters: PlotParameters,
+    retry_policy: RetryPolicy,
+    connection_options: ConnectionOptions,
+    cache_options: Option<CacheOptions>,
+    rate_limiter: Arc<RateLimiter>,
+    fixtures: Option<Fixtures>,
+    transport: Option<Arc<dyn Transport>>,
+}
+
+impl Default for WsdClientBuilder {
+    fn default() -> WsdClientBuilder {
+        WsdClientBuilder {
+            base_url: DEFAULT_BASE_URL.to_owned(),
+            default_parameters: PlotParameters::default(),
+            retry_policy: RetryPolicy::default(),
+            connection_options: ConnectionOptions::default(),
+            cache_options: None,
+            rate_limiter: RateLimiter::global(),
+            fixtures: None,
+            transport: None,
+        }
+    }
+}
+
+impl WsdClientBuilder {
+    pub fn new() -> WsdClientBuilder {
+        WsdClientBuilder::default()
+    }
+
+    /// Set address of the API. E.g. `http://localhost:8080` for local stand-in server
+    /// or address of internal mirror. Requests are sent to `<base_url>/index.php`
+    pub fn base_url(mut self, base_url: &str) -> WsdClientBuilder {
+        self.base_url = base_url.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Set parameters used by `WsdClient::get_diagram_with_defaults`
+    pub fn default_parameters(mut self, default_parameters: PlotParameters) -> WsdClientBuilder {
+        self.default_parameters = default_parameters;
+        self
+    }
+
+    /// Set policy of retrying failed requests. Use `RetryPolicy::none()` to disable retries
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> WsdClientBuilder {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Set all connection options (timeouts, proxy, TLS) at once
+    pub fn connection_options(mut self, connection_options: ConnectionOptions) -> WsdClientBuilder {
+        self.connection_options = connection_options;
+        self
+    }
+
+    /// Set timeout for establishing connection
+    pub fn connect_timeout(mut self, timeout: Duration) -> WsdClientBuilder {
+        self.connection_options.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Set timeout for each request. It includes connecting, sending request and reading response
+    pub fn timeout(mut self, timeout: Duration) -> WsdClientBuilder {
+        self.connection_options.timeout = Some(timeout);
+        self
+    }
+
+    /// Set proxy for all requests. E.g. `http://proxy.local:3128`.
+    /// By default proxy is taken from `HTTPS_PROXY`/`HTTP_PROXY` environment variables
+    pub fn proxy(mut self, proxy: &str) -> WsdClientBuilder {
+        self.connection_options.proxy = Some(proxy.to_owned());
+        self
+    }
+
+    /// Set scheme (http or https) used to connect to API. It replaces scheme of the base url
+    pub fn scheme(mut self, scheme: Scheme) -> WsdClientBuilder {
+        self.connection_options.scheme = Some(scheme);
+        self
+    }
+
+    /// Add trusted root certificate from PEM file
+    pub fn add_root_certificate<P: Into<PathBuf>>(mut self, path: P) -> WsdClientBuilder {
+        self.connection_options.root_certificates.push(path.into());
+        self
+    }
+
+    /// Enable on-disk cache of rendered diagrams. Cache hits skip both requests to API.
+    /// By default cache is disabled. Cache is not used together with fixtures
+    pub fn cache(mut self, cache_options: CacheOptions) -> WsdClientBuilder {
+        self.cache_options = Some(cache_options);
+        self
+    }
+
+    /// Set rate limiter of requests to API. By default `RateLimiter::global()` is used,
+    /// so all clients in the process share the same limit
+    pub fn rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> WsdClientBuilder {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Record requests to API into fixtures or replay them from fixtures.
+    /// With fixtures async methods perform requests synchronously.
+    /// Cache is disabled, otherwise diagrams found in cache would not be recorded
+    pub fn fixtures(mut self, fixtures: Fixtures) -> WsdClientBuilder {
+        self.fixtures = Some(fixtures);
+        self
+    }
+
+    /// Set HTTP layer used to send requests. E.g. `InMemoryTransport` in tests.
+    /// By default `ReqwestTransport` is used. Connection options (timeouts, proxy, TLS)
+    /// apply only to the default transport. Async methods perform requests with
+    /// `Transport::post_form_async` and `Transport::get_async`
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> WsdClientBuilder {
+        self.transport = Some(transport);
+        self
+    }
+
+    pub fn build(self) -> Result<WsdClient, WsdError> {
+        let base_url = self.connection_options.apply_scheme(&self.base_url);
+        let transport = match self.transport {
+            Some(transport) => transport,
+            None => {
+                let (http_client, async_http_client) = reqwest_clients(&self.connection_options)?;
+                Arc::new(ReqwestTransport::with_async_client(http_client, async_http_client))
+            }
+        };
+        let transport = match self.fixtures {
+            Some(ref fixtures) => Arc::new(FixtureTransport::new(transport, fixtures.clone(), &base_url)),
+            None => transport,
+        };
+
+        Ok(WsdClient {
+            transport,
+            base_url,
+            default_parameters: self.default_parameters,
+            retry_policy: self.retry_policy,
+            cache: match (self.cache_options, &self.fixtures) {
+                (Some(cache_options), None) => Some(DiskCache::new(cache_options)?),
+                _ => None,
+            },
+            rate_limiter: self.rate_limiter,
+            fixtures: self.fixtures,
+        })
+    }
+}
+
+// Creates blocking and non-blocking http clients with the same connection options
+fn reqwest_clients(options: &ConnectionOptions) -> Result<(reqwest::Client, AsyncClient), WsdError> {
+    let proxy = options.reqwest_proxy()?;
+
+    let mut http_client = reqwest::Client::builder();
+    let mut async_http_client = AsyncClient::builder();
+    if let Some(connect_timeout) = options.connect_timeout {
+        http_client = http_client.connect_timeout(connect_timeout);
+        async_http_client = async_http_client.connect_timeout(connect_timeout);
+    }
+    if let Some(timeout) = options.timeout {
+        http_client = http_client.timeout(timeout);
+        async_http_client = async_http_client.timeout(timeout);
+    }
+    if let Some(proxy) = proxy {
+        http_client = http_client.proxy(proxy.clone());
+        async_http_client = async_http_client.proxy(proxy);
+    }
+    // Certificates cannot be cloned, so they are loaded for each client
+    for certificate in options.reqwest_certificates()? {
+        http_client = http_client.add_root_certificate(certificate);
+    }
+    for certificate in options.reqwest_certificates()? {
+        async_http_client = async_http_client.add_root_certificate(certificate);
+    }
+
+    Ok((
+        http_client.build().map_err(WsdError::ClientInit)?,
+        async_http_client.build().map_err(WsdError::ClientInit)?,
+    ))
+}
+
+impl Default for WsdClient {
+    fn default() -> WsdClient {
+        WsdClient::new()
+    }
+}
+
+impl WsdClient {
+    /// Create client for public websequencediagrams API with default parameters.
+    ///
+    /// Panics if http client cannot be initialised. Use `WsdClient::builder()` to handle this error.
+    pub fn new() -> WsdClient {
+        WsdClientBuilder::new()
+            .build()
+            .expect("WsdClient::new()")
+    }
+
+    pub fn builder() -> WsdClientBuilder {
+        WsdClientBuilder::new()
+    }
+
+    /// Address of the API used by this client
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Parameters used by `get_diagram_with_defaults`
+    pub fn default_parameters(&self) -> &PlotParameters {
+        &self.default_parameters
+    }
+
+    /// Policy of retrying failed requests
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// On-disk cache of rendered diagrams if it is enabled
+    pub fn cache(&self) -> Option<&DiskCache> {
+        self.cache.as_ref()
+    }
+
+    /// Rate limiter of requests to API
+    pub fn rate_limiter(&self) -> &Arc<RateLimiter> {
+        &self.rate_limiter
+    }
+
+    /// Fixtures which are recorded or replayed if they are set
+    pub fn fixtures(&self) -> Option<&Fixtures> {
+        self.fixtures.as_ref()
+    }
+
+    /// HTTP layer used to send requests
+    pub fn transport(&self) -> &Arc<dyn Transport> {
+        &self.transport
+    }
+
+    /// plot diagram using default parameters of the client
+    pub fn get_diagram_with_defaults(&self, spec: &str) -> Result<WSDResult, WsdError> {
+        self.get_diagram(spec, &self.default_parameters)
+    }
+
+    /// plot diagram using websequncediagrams API
+    ///
+    /// It is a combination of `submit` and `RenderTicket::download`.
+    /// If cache is enabled, diagram is taken from it when possible.
+    pub fn get_diagram(&self, spec: &str, parameters: &PlotParameters) -> Result<WSDResult, WsdError> {
+        if let Some(cached) = self.cached_diagram(spec, parameters) {
+            return Ok(WSDResult::from_cache(cached));
+        }
+        let result = self.submit(spec, parameters)?.download()?;
+        self.store_in_cache(spec, parameters, &result);
+        Ok(result)
+    }
+
+    /// plot diagram using websequncediagrams API without blocking.
+    ///
+    /// Returned future should be run on tokio runtime.
+    pub fn get_diagram_async(&self, spec: &str, parameters: &PlotParameters)
+        -> impl Future<Item=WSDResult, Error=WsdError> {
+        if let Some(cached) = self.cached_diagram(spec, parameters) {
+            return future::Either::A(future::ok(WSDResult::from_cache(cached)));
+        }
+        let client = self.clone();
+        let spec_owned = spec.to_owned();
+        let parameters_owned = parameters.clone();
+        future::Either::B(
+            self.submit_async(spec, parameters)
+                .and_then(|ticket| ticket.download_async())
+                .map(move |result| {
+                    client.store_in_cache(&spec_owned, &parameters_owned, &result);
+                    result
+                })
+        )
+    }
+
+    /// plot diagram and write it into `writer` without holding the whole diagram in memory.
+    ///
+    /// Errors in the diagram and actual format are returned in the result.
+    /// If cache is enabled, diagram is taken from it when possible.
+    pub fn render_to_writer<W: Write>(&self, spec: &str, parameters: &PlotParameters, writer: &mut W)
+        -> Result<WSDStreamResult, WsdError> {
+        if let Some(cached) = self.cached_diagram(spec, parameters) {
+            writer.write_all(&cached.diagram)
+                .and_then(|_| writer.flush())
+                .map_err(WsdError::Output)?;
+            return Ok(WSDStreamResult {
+                bytes_written: cached.diagram.len() as u64,
+                errors: cached.errors,
+                actual_format: cached.actual_format,
+                message_attempts: 0,
+                image_attempts: 0,
+                response: cached.response,
+                from_cache: true,
+            });
+        }
+
+        let ticket = self.submit(spec, parameters)?;
+        let cache_writer = match self.cache {
+            // Downgraded diagrams are not cached. Next time API key may be correct
+            Some(ref cache) if ticket.actual_format == parameters.format => cache.writer().ok(),
+            _ => None,
+        };
+        let mut tee = TeeWriter {
+            writer,
+            cache_writer,
+        };
+        let result = ticket.download_to_writer(&mut tee)?;
+        if let Some(cache_writer) = tee.cache_writer {
+            // Diagram is already written to output, so failure to cache it is not an error
+            let _ = cache_writer.commit(
+                &DiskCache::key(spec, parameters),
+                result.actual_format.clone(),
+                result.errors.clone(),
+                result.response.clone(),
+            );
+        }
+        Ok(result)
+    }
+
+    // Takes diagram from cache if cache is enabled
+    fn cached_diagram(&self, spec: &str, parameters: &PlotParameters) -> Option<CachedDiagram> {
+        self.cache
+            .as_ref()
+            .and_then(|cache| cache.get(&DiskCache::key(spec, parameters)))
+    }
+
+    // Stores diagram in cache if cache is enabled.
+    // Downgraded diagrams are not cached. Next time API key may be correct.
+    // Failure to cache diagram is not an error, diagram is already obtained
+    fn store_in_cache(&self, spec: &str, parameters: &PlotParameters, result: &WSDResult) {
+        if let Some(ref cache) = self.cache {
+            if result.actual_format == parameters.format {
+                let _ = cache.put(&DiskCache::key(spec, parameters), &CachedDiagram {
+                    diagram: result.diagram.clone(),
+                    actual_format: result.actual_format.clone(),
+                    errors: result.errors.clone(),
+                    response: result.response.clone(),
+                });
+            }
+        }
+    }
+
+    /// Send diagram specification to API without downloading the diagram.
+    ///
+    /// Returned ticket contains url of the diagram, so it can be embedded as a link
+    /// or downloaded later.
+    pub fn submit(&self, spec: &str, parameters: &PlotParameters) -> Result<RenderTicket, WsdError> {
+        let params = form_params(spec, parameters);
+
+        // URL for first request
+        let first_request_url = format!("{}/index.php", self.base_url);
+        retry(&self.retry_policy, || {
+            self.rate_limited(|| self.request_message(&first_request_url, &params))
+        })
+            .and_then(|(first_response, message_attempts)| {
+                RenderTicket::from_response(self.clone(), first_response, message_attempts)
+            })
+            .map_err(|err| redact_api_key(err, parameters.api_key.as_ref()))
+    }
+
+    /// Send diagram specification to API without blocking. Async counterpart of `submit`.
+    ///
+    /// Request is sent with `Transport::post_form_async`
+    pub fn submit_async(&self, spec: &str, parameters: &PlotParameters)
+        -> impl Future<Item=RenderTicket, Error=WsdError> {
+        let params = form_params(spec, parameters);
+        // URL for first request
+        let first_request_url = format!("{}/index.php", self.base_url);
+        let transport = self.transport.clone();
+        let rate_limiter = self.rate_limiter.clone();
+        let client = self.clone();
+        let api_key = parameters.api_key.clone();
+        retry_async(self.retry_policy.clone(), move || {
+            let (transport, url, params) = (transport.clone(), first_request_url.clone(), params.clone());
+            rate_limited_async(&rate_limiter, move || request_message_async(&*transport, url, &params))
+        })
+            .and_then(move |(first_response, message_attempts)| {
+                RenderTicket::from_response(client, first_response, message_attempts)
+            })
+            .map_err(move |err| redact_api_key(err, api_key.as_ref()))
+    }
+
+    // Waits for rate limiter before request and pauses it if API responded with 429
+    fn rate_limited<T, F>(&self, request: F) -> Result<T, WsdError>
+        where
+            F: FnOnce() -> Result<T, WsdError>,
+    {
+        self.rate_limiter.acquire();
+        let result = request();
+        if let Err(ref err) = result {
+            self.rate_limiter.observe(err);
+        }
+        result
+    }
+
+    // Sends diagram specification to API. Response contains url of the diagram and errors
+    fn request_message(&self, url: &str, params: &[(String, String)]) -> Result<WebSequenceDiagramResponse, WsdError> {
+        log_request("POST", url, params);
+        let started = Instant::now();
+        message_response(url, started, self.transport.post_form(url, params))
+    }
+
+    // Sends request for the diagram. Body of returned response is the diagram
+    fn open_image(&self, url: &str) -> Result<Box<dyn Read + Send>, WsdError> {
+        log_request("GET", url, &[]);
+        let started = Instant::now();
+        let mut response = self.transport.get_streaming(url)
+            .map_err(|err| log_failure("GET", url, started, err))?;
+        debug!("GET {} -> {} in {} ms", url, response.status, elapsed_ms(started));
+        if !is_success(response.status) {
+            let mut body = vec![];
+            // Body is only informational here, so error reading it is ignored
+            let _ = std::io::copy(&mut response.body, &mut body);
+            return Err(status_error(url, response.status, response.header("retry-after"), &body));
+        }
+        Ok(response.body)
+    }
+
+    // Downloads the diagram
+    fn request_image(&self, url: &str) -> Result<Vec<u8>, WsdError> {
+        let started = Instant::now();
+        let mut response = self.open_image(url)?;
+
+        let mut data = vec![];
+        std::io::copy(&mut response, &mut data)
+            .map_err(|err| WsdError::Io { url: url.to_owned(), source: err })?;
+        debug!("GET {} downloaded {} bytes in {} ms", url, data.len(), elapsed_ms(started));
+        Ok(data)
+    }
+}
+
+/// Represent diagram accepted by API but not downloaded yet
+///
+/// It is obtained from `WsdClient::submit`. Diagram can be downloaded using `download`
+/// or embedded as a link using `url`.
+#[derive(Debug, Clone)]
+pub struct RenderTicket {
+    client: WsdClient,
+
+    /// url of the diagram relative to API address. E.g. "?png=mscKTO107"
+    pub image_path: String,
+
+    /// Actual format of the diagram. It may be different from requested
+    pub actual_format: Format,
+
+    /// Errors in the diagram. Errors are not fatal
+    pub errors: Vec<DiagramError>,
+
+    /// Number of attempts made to send diagram specification
+    pub message_attempts: u32,
+
+    /// Full response of API on the request for diagram
+    pub response: WebSequenceDiagramResponse,
+}
+
+impl RenderTicket {
+    fn from_response(client: WsdClient, response: WebSequenceDiagramResponse, message_attempts: u32)
+        -> Result<RenderTicket, WsdError> {
+        let ticket = RenderTicket {
+            image_path: response.img.clone(),
+            actual_format: determine_actual_format(&response.img)?,
+            errors: parse_diagram_errors(&response.errors)?,
+            message_attempts,
+            response,
+            client,
+        };
+        debug!("diagram url: {}", ticket.url());
+        Ok(ticket)
+    }
+
+    /// Absolute url of the diagram. E.g. "http://www.websequencediagrams.com/index.php?png=mscKTO107"
+    pub fn url(&self) -> String {
+        format!("{}/index.php{}", self.client.base_url, self.image_path)
+    }
+
+    /// Download the diagram
+    pub fn download(&self) -> Result<WSDResult, WsdError> {
+        let url = self.url();
+        // Second request contains actual diagram
+        let (data, image_attempts) = retry(&self.client.retry_policy, || {
+            self.client.rate_limited(|| self.client.request_image(&url))
+        })?;
+        Ok(self.clone().into_result(data, image_attempts))
+    }
+
+    /// Download the diagram writing it into `writer` as it is received.
+    ///
+    /// Failed request is retried only until the diagram starts arriving.
+    /// Error after some data is written to `writer` is returned as is.
+    pub fn download_to_writer<W: Write>(&self, writer: &mut W) -> Result<WSDStreamResult, WsdError> {
+        let url = self.url();
+        let started = Instant::now();
+        let (mut response, image_attempts) = retry(&self.client.retry_policy, || {
+            self.client.rate_limited(|| self.client.open_image(&url))
+        })?;
+
+        let mut bytes_written = 0u64;
+        let mut buf = [0u8; 64 * 1024];
+        loop {
+            let n = match response.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(WsdError::Io { url, source: err }),
+            };
+            writer.write_all(&buf[..n]).map_err(WsdError::Output)?;
+            bytes_written += n as u64;
+        }
+        writer.flush().map_err(WsdError::Output)?;
+        debug!("GET {} downloaded {} bytes in {} ms", url, bytes_written, elapsed_ms(started));
+
+        Ok(WSDStreamResult {
+            bytes_written,
+            errors: self.errors.clone(),
+            actual_format: self.actual_format.clone(),
+            message_attempts: self.message_attempts,
+            image_attempts,
+            response: self.response.clone(),
+            from_cache: false,
+        })
+    }
+
+    /// Download the diagram without blocking. Async counterpart of `download`.
+    ///
+    /// Request is sent with `Transport::get_async`
+    pub fn download_async(&self) -> impl Future<Item=WSDResult, Error=WsdError> {
+        let url = self.url();
+        let transport = self.client.transport.clone();
+        let rate_limiter = self.client.rate_limiter.clone();
+        let ticket = self.clone();
+        // Second request contains actual diagram
+        retry_async(self.client.retry_policy.clone(), move || {
+            let (transport, url) = (transport.clone(), url.clone());
+            rate_limited_async(&rate_limiter, move || request_image_async(&*transport, url))
+        })
+            .map(move |(data, image_attempts)| ticket.into_result(data, image_attempts))
+    }
+
+    fn into_result(self, diagram: Vec<u8>, image_attempts: u32) -> WSDResult {
+        WSDResult {
+            diagram,
+            errors: self.errors,
+            actual_format: self.actual_format,
+            message_attempts: self.message_attempts,
+            image_attempts,
+            response: self.response,
+            from_cache: false,
+        }
+    }
+}
+
+impl WSDResult {
+    fn from_cache(cached: CachedDiagram) -> WSDResult {
+        WSDResult {
+            diagram: cached.diagram,
+            errors: cached.errors,
+            actual_format: cached.actual_format,
+            message_attempts: 0,
+            image_attempts: 0,
+            response: cached.response,
+            from_cache: true,
+        }
+    }
+}
+
+// Writes diagram both into output and into cache.
+// Errors writing into cache only disable caching
+struct TeeWriter<'a, W: Write> {
+    writer: &'a mut W,
+    cache_writer: Option<CacheWriter>,
+}
+
+impl<'a, W: Write> Write for TeeWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.writer.write(buf)?;
+        let cache_failed = match self.cache_writer {
+            Some(ref mut cache_writer) => cache_writer.write_all(&buf[..n]).is_err(),
+            None => false,
+        };
+        if cache_failed {
+            self.cache_writer = None;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
 }
 
 /// plot diagram using websequncediagrams public API
-pub fn get_diagram(spec: &str, parameters: &PlotParameters) -> Result<WSDResult, Box<Error>> {
+///
+/// It is a shortcut for `WsdClient::new().get_diagram(spec, parameters)`.
+/// Create `WsdClient` and reuse it when plotting many diagrams.
+pub fn get_diagram(spec: &str, parameters: &PlotParameters) -> Result<WSDResult, WsdError> {
+    WsdClient::builder()
+        .build()?
+        .get_diagram(spec, parameters)
+}
+
+/// plot diagram using websequncediagrams public API without blocking.
+///
+/// Async counterpart of `get_diagram`. Returned future should be run on tokio runtime.
+pub fn get_diagram_async(spec: &str, parameters: &PlotParameters)
+    -> impl Future<Item=WSDResult, Error=WsdError> {
+    let spec = spec.to_owned();
+    let parameters = parameters.clone();
+    future::result(WsdClient::builder().build())
+        .and_then(move |client| client.get_diagram_async(&spec, &parameters))
+}
+
+// Async counterpart of `WsdClient::rate_limited`. Request is created only after token is taken
+fn rate_limited_async<T, F, R>(rate_limiter: &Arc<RateLimiter>, request: F) -> impl Future<Item=T, Error=WsdError>
+    where
+        F: FnOnce() -> R,
+        R: Future<Item=T, Error=WsdError>,
+{
+    let observer = rate_limiter.clone();
+    rate_limiter
+        .acquire_async()
+        .and_then(|_| request())
+        .map_err(move |err| {
+            observer.observe(&err);
+            err
+        })
+}
+
+fn request_message_async(transport: &dyn Transport, url: String, params: &[(String, String)])
+    -> impl Future<Item=WebSequenceDiagramResponse, Error=WsdError> {
+    log_request("POST", &url, params);
+    let started = Instant::now();
+    transport
+        .post_form_async(&url, params)
+        .then(move |response| message_response(&url, started, response))
+}
+
+fn request_image_async(transport: &dyn Transport, url: String) -> impl Future<Item=Vec<u8>, Error=WsdError> {
+    log_request("GET", &url, &[]);
+    let started = Instant::now();
+    transport
+        .get_async(&url)
+        .then(move |response| {
+            let response = response.map_err(|err| log_failure("GET", &url, started, err))?;
+            debug!("GET {} -> {} in {} ms, {} bytes", url, response.status, elapsed_ms(started), response.body.len());
+            if !is_success(response.status) {
+                return Err(status_error(&url, response.status, response.header("retry-after"), &response.body));
+            }
+            Ok(response.body)
+        })
+}
+
+// Logs response on the request for diagram and parses it
+fn message_response(url: &str, started: Instant, response: Result<TransportResponse, WsdError>)
+    -> Result<WebSequenceDiagramResponse, WsdError> {
+    let response = response.map_err(|err| log_failure("POST", url, started, err))?;
+    log_message_response(url, response.status, &response.body, started);
+    parse_wsd_response(url, response.status, response.header("retry-after"), &response.body)
+}
+
+// Parses response on the first request. It contains url of the diagram and errors
+fn parse_wsd_response(url: &str, status: u16, retry_after: Option<&str>, body: &[u8])
+    -> Result<WebSequenceDiagramResponse, WsdError> {
+    if !is_success(status) {
+        return Err(status_error(url, status, retry_after, body));
+    }
+
+    serde_json::from_slice(body)
+        .map_err(|err|
+            WsdError::Decode {
+                body: String::from_utf8_lossy(body).to_string(),
+                source: err,
+            }
+        )
+}
+
+// Error for unsuccessful response. 429 Too Many Requests is distinguished, so rate limiter can react
+fn status_error(url: &str, status: u16, retry_after: Option<&str>, body: &[u8]) -> WsdError {
+    let body = String::from_utf8_lossy(body).to_string();
+    if status == StatusCode::TOO_MANY_REQUESTS.as_u16() {
+        return WsdError::RateLimited {
+            url: url.to_owned(),
+            retry_after: retry_after.and_then(parse_retry_after),
+            body,
+        };
+    }
+    WsdError::Status {
+        url: url.to_owned(),
+        status,
+        body,
+    }
+}
+
+fn is_success(status: u16) -> bool {
+    (200..300).contains(&status)
+}
+
+// Logs request to API. Form fields are logged only at trace level and without API key
+fn log_request(method: &str, url: &str, form: &[(String, String)]) {
+    debug!("{} {}", method, url);
+    if !form.is_empty() {
+        trace!("{} {} form: {:?}", method, url, redacted_form(form));
+    }
+}
+
+// Logs response on the request for diagram. Raw body is logged only at trace level
+fn log_message_response(url: &str, status: u16, body: &[u8], started: Instant) {
+    debug!("POST {} -> {} in {} ms, {} bytes", url, status, elapsed_ms(started), body.len());
+    trace!("POST {} response: {}", url, String::from_utf8_lossy(body));
+}
+
+// Logs failed request and returns the error
+fn log_failure(method: &str, url: &str, started: Instant, err: WsdError) -> WsdError {
+    debug!("{} {} failed in {} ms: {}", method, url, elapsed_ms(started), err);
+    err
+}
+
+// Removes API key from the error. Server may echo request in the response
+fn redact_api_key(err: WsdError, api_key: Option<&Secret>) -> WsdError {
+    match api_key {
+        Some(api_key) => err.redact(api_key),
+        None => err,
+    }
+}
+
+fn elapsed_ms(started: Instant) -> u128 {
+    started.elapsed().as_millis()
+}
+
+fn parse_diagram_errors(raw_errors: &[String]) -> Result<Vec<DiagramError>, WsdError> {
+    raw_errors
+        .iter()
+        .map(|error| DiagramError::from_wsd_error_str(error))
+        .collect()
+}
+
+// Form fields of the request for the diagram
+fn form_params(spec: &str, parameters: &PlotParameters) -> Vec<(String, String)> {
     // if send request for pdf but key is incorrect png in returned
     let mut params = vec![
         ("message".to_owned(), spec.to_owned()),
@@ -43,7 +868,7 @@ pub fn get_diagram(spec: &str, parameters: &PlotParameters) -> Result<WSDResult,
         ("apiVersion".to_owned(), "1".to_owned()),
     ];
     if let Some(ref api_key) = parameters.api_key {
-        params.push(("apikey".to_owned(), api_key.clone()));
+        params.push(("apikey".to_owned(), api_key.expose().to_owned()));
     }
     if let Some(ref paper_size) = parameters.paper_size {
         params.push(("paper".to_owned(), paper_size.wsd_value()));
@@ -54,112 +879,206 @@ pub fn get_diagram(spec: &str, parameters: &PlotParameters) -> Result<WSDResult,
     if let Some(ref scale) = parameters.scale {
         params.push(("scale".to_owned(), format!("{}", scale)));
     }
-
-    // URL for first request
-    let first_request_url = "http://www.websequencediagrams.com/index.php";
-    let first_response = reqwest::Client::new()
-        .post(first_request_url)
-        .form(&params)
-        .send();
-
-    let first_response: WebSequenceDiagramResponse = match first_response {
-        Ok(mut r) => {
-            let mut v = vec![];
-            // Save the response, so we can check it if something going wrong
-            std::io::copy(&mut r, &mut v)
-                .map_err(|err| format!("error reading response from server {} : {:?}", first_request_url, err))?;
-
-            if !r.status().is_success() {
-                return Err(format!(
-                    "Error response from server: {} HTTP code={:?} response={}",
-                    first_request_url,
-                    r.status(),
-                    String::from_utf8_lossy(&v)
-                ).into())
-            }
-
-            serde_json::from_reader(&v[..])
-                .map_err(|err|
-                    format!(
-                        "Cannot deserialize websequencegiagram response: {:?} Response: {}",
-                        err,
-                        String::from_utf8_lossy(&v)
-                    )
-                )
-        }
-        Err(err) => {
-            Err(format!("error sending request to {} : {}", first_request_url, err))
-        }
-    }?;
-
-    let actual_format = determine_actual_format(&first_response.img)
-        .map_err(|err| format!("cannot determine actual format from url: {} : {:?}", &first_response.img, err))?;
-
-    let second_request_url = format!("http://www.websequencediagrams.com/index.php{}", first_response.img);
-    // Second request contains actual diagram
-    let mut second_response = reqwest::Client::new()
-        .get(&second_request_url)
-        .send()
-        .map_err(|err| format!("Error sending request for diagram to {} : {:?}", second_request_url, err))?;
-
-    if !second_response.status().is_success() {
-        return Err(format!("Request for diagram was unsuccesfull url: {} code: {:?}", second_request_url, second_response.status()).into());
-    }
-
-    let mut data = vec![];
-    std::io::copy(&mut second_response, &mut data)
-        .map_err(|err|
-                     format!("Error reading diagram from {} : {:?}", second_request_url, err)
-        )?;
-
-    let errors_parsed = first_response.errors
-        .iter()
-        .map(|error| DiagramError::from_wsd_error_str(error));
-    let mut errors = vec![];
-    for error in errors_parsed {
-        match error {
-            Ok(error) => errors.push(error),
-            Err(err) => return Err(format!("cannot parse wsd error message  {:?}",err).into())
-        }
-    }
-
-    Ok(WSDResult {
-        diagram: data,
-        errors,
-        actual_format,
-    })
+    params
 }
 
-fn determine_actual_format(url: &str) -> Result<Format, Box<Error>> {
+fn determine_actual_format(url: &str) -> Result<Format, WsdError> {
     let re = Regex::new(r"(?ix)
 \?
 (?P<format>\w+)  # format
 =
 .*
-")?;
+").expect("regex for diagram url is valid");
 
-    let caps = if let Some(caps) = re.captures(url) {
-        caps
-    } else {
-        return Err("Error parsing diagram url.".into())
-    };
-    let format_str = if let Some(format_match) = caps.name("format"){
-        format_match.as_str()
-    } else {
-        return Err("Error parsing diagram url. Group `format` not found".into())
+    let format_str = match re.captures(url).and_then(|caps| caps.name("format")) {
+        Some(format_match) => format_match.as_str(),
+        None => return Err(WsdError::InvalidImageUrl { url: url.to_owned() }),
     };
     match Format::from_str(format_str) {
         Some(x) => Ok(x),
         None => {
-            Err(format!("unknown format in diagram url. Known formats are: {}. Got: {}", Format::help_str(), format_str).into())
+            Err(WsdError::UnknownFormat { url: url.to_owned(), format: format_str.to_owned() })
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::types::{DiagramError, Format};
-    use crate::client::determine_actual_format;
+    use crate::cache::CacheOptions;
+    use crate::types::{DiagramError, Format, PlotParameters};
+    use crate::client::{determine_actual_format, form_params, parse_wsd_response, status_error, WsdClient};
+    use crate::error::WsdError;
+    use crate::rate_limit::{RateLimiter, DEFAULT_RATE_LIMIT_PAUSE};
+    use crate::retry::RetryPolicy;
+    use crate::transport::{redacted_form, InMemoryTransport, Transport, TransportFuture, TransportResponse};
+
+    use futures::future;
+
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc::{channel, Receiver};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // Minimal stand-in for websequencediagrams server. Answers connections in order
+    // with given (HTTP status, body) pairs. Status 0 means connection is closed without response.
+    // Returns base url of the server and receiver of raw requests (request line and body).
+    fn stand_in_server(responses: Vec<(u16, Vec<u8>)>) -> (String, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                sender.send(read_request(&mut stream)).unwrap();
+                if status == 0 {
+                    continue;
+                }
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Stand-in\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                ).unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        (base_url, receiver)
+    }
+
+    fn read_request(stream: &mut TcpStream) -> String {
+        let mut data = vec![];
+        let mut buf = [0u8; 1024];
+        loop {
+            let n = stream.read(&mut buf).unwrap();
+            data.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&data).to_string();
+            if let Some(headers_end) = text.find("\r\n\r\n") {
+                let content_length = text[..headers_end]
+                    .lines()
+                    .filter_map(|line| {
+                        let mut parts = line.splitn(2, ':');
+                        match (parts.next(), parts.next()) {
+                            (Some(name), Some(value)) if name.eq_ignore_ascii_case("content-length") =>
+                                value.trim().parse::<usize>().ok(),
+                            _ => None,
+                        }
+                    })
+                    .next()
+                    .unwrap_or(0);
+                if data.len() >= headers_end + 4 + content_length || n == 0 {
+                    let request_line = text.lines().next().unwrap_or("").to_owned();
+                    return format!("{}\n{}", request_line, &text[headers_end + 4..]);
+                }
+            }
+            if n == 0 {
+                return text;
+            }
+        }
+    }
+
+    #[test]
+    fn parse_wsd_response_test() {
+        let body = br#"{"img": "?png=mscKTO107", "page": 0, "numPages": 2, "errors": [], "remaining": 41}"#;
+        let response = parse_wsd_response("http://localhost/index.php", 200, None, body).unwrap();
+        assert_eq!(response.img, "?png=mscKTO107");
+        assert_eq!(response.page, Some(0));
+        assert_eq!(response.num_pages, Some(2));
+        assert_eq!(response.other.get("remaining"), Some(&serde_json::Value::from(41)));
+
+        let serialized = serde_json::to_value(&response).unwrap();
+        assert_eq!(serialized, serde_json::from_slice::<serde_json::Value>(body).unwrap());
+    }
+
+    #[test]
+    fn status_error_test() {
+        match status_error("http://localhost/index.php", 429, Some("30"), b"slow down") {
+            WsdError::RateLimited { retry_after, body, .. } => {
+                assert_eq!(retry_after, Some(Duration::from_secs(30)));
+                assert_eq!(body, "slow down");
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+        let err = status_error("http://localhost/index.php", 404, Some("30"), b"");
+        assert_eq!(err.status(), Some(404));
+    }
+
+    #[test]
+    fn redacted_form_test() {
+        let parameters = PlotParameters {
+            api_key: Some("secret".into()),
+            ..PlotParameters::default()
+        };
+        let form = form_params("A->B: text", &parameters);
+        let logged = format!("{:?}", redacted_form(&form));
+        assert!(logged.contains("A->B: text"));
+        assert!(logged.contains("<redacted>"));
+        assert!(!logged.contains("secret"));
+    }
+
+    #[test]
+    fn client_uses_base_url_test() {
+        let (base_url, requests) = stand_in_server(vec![
+            (200, br#"{"img": "?png=mscKTO107", "errors": ["Line 1: Syntax error."]}"#.to_vec()),
+            (200, b"PNG DATA".to_vec()),
+        ]);
+        let client = WsdClient::builder()
+            .base_url(&format!("{}/", base_url))
+            .build()
+            .unwrap();
+        let rez = client.get_diagram_with_defaults("A->B: text").unwrap();
+        assert_eq!(rez.diagram, b"PNG DATA".to_vec());
+        assert_eq!(rez.actual_format, Format::Png);
+        assert_eq!(rez.errors, vec![DiagramError::from_wsd_error_str("Line 1: Syntax error.").unwrap()]);
+
+        let first_request = requests.recv().unwrap();
+        assert!(first_request.starts_with("POST /index.php HTTP/1.1"));
+        assert!(first_request.contains("message=A-%3EB%3A+text"));
+        assert!(first_request.contains("format=png"));
+        let second_request = requests.recv().unwrap();
+        assert!(second_request.starts_with("GET /index.php?png=mscKTO107 HTTP/1.1"));
+    }
+
+    #[test]
+    fn client_get_diagram_async_test() {
+        let (base_url, requests) = stand_in_server(vec![
+            (200, br#"{"img": "?svg=mscKTO107", "errors": []}"#.to_vec()),
+            (200, b"SVG DATA".to_vec()),
+        ]);
+        let client = WsdClient::builder()
+            .base_url(&base_url)
+            .build()
+            .unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let rez = runtime.block_on(client.get_diagram_async("A->B: text", &Default::default())).unwrap();
+        assert_eq!(rez.diagram, b"SVG DATA".to_vec());
+        assert_eq!(rez.actual_format, Format::Svg);
+        assert!(rez.errors.is_empty());
+
+        assert!(requests.recv().unwrap().starts_with("POST /index.php HTTP/1.1"));
+        assert!(requests.recv().unwrap().starts_with("GET /index.php?svg=mscKTO107 HTTP/1.1"));
+    }
+
+    #[test]
+    fn client_reports_http_error_test() {
+        let (base_url, _requests) = stand_in_server(vec![
+            (500, b"Internal Server Error".to_vec()),
+        ]);
+        let client = WsdClient::builder()
+            .base_url(&base_url)
+            .retry_policy(RetryPolicy::none())
+            .build()
+            .unwrap();
+        match client.get_diagram_with_defaults("A->B: text") {
+            Err(WsdError::Status { status, body, .. }) => {
+                assert_eq!(status, 500);
+                assert_eq!(body, "Internal Server Error");
+            },
+            Err(err) => panic!("Status error expected. Instead got: {:?}", err),
+            Ok(_) => panic!("Status error expected. Instead got diagram"),
+        }
+    }
 
     #[test]
     fn determine_actual_format_test() {
@@ -169,6 +1088,413 @@ mod tests {
         assert_eq!(determine_actual_format("?png=mscKTO107").unwrap(), Format::Png);
         assert_eq!(determine_actual_format("?pdf=mscKTO107").unwrap(), Format::Pdf);
         assert_eq!(determine_actual_format("?svg=mscKTO107").unwrap(), Format::Svg);
-        assert!(determine_actual_format("?xxx=mscKTO107").is_err());
+        match determine_actual_format("?xxx=mscKTO107") {
+            Err(WsdError::UnknownFormat { format, .. }) => assert_eq!(format, "xxx"),
+            rez => panic!("UnknownFormat error expected. Instead got: {:?}", rez),
+        }
+        match determine_actual_format("mscKTO107") {
+            Err(WsdError::InvalidImageUrl { .. }) => {},
+            rez => panic!("InvalidImageUrl error expected. Instead got: {:?}", rez),
+        }
     }
-}
\ No newline at end of file
+
+    fn fast_retry_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            backoff_base: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn client_retries_transient_errors_test() {
+        let (base_url, _requests) = stand_in_server(vec![
+            (503, b"Service Unavailable".to_vec()),
+            (502, b"Bad Gateway".to_vec()),
+            (200, br#"{"img": "?png=mscKTO107", "errors": []}"#.to_vec()),
+            (0, vec![]),
+            (200, b"PNG DATA".to_vec()),
+        ]);
+        let client = WsdClient::builder()
+            .base_url(&base_url)
+            .retry_policy(fast_retry_policy(3))
+            .build()
+            .unwrap();
+        let rez = client.get_diagram_with_defaults("A->B: text").unwrap();
+        assert_eq!(rez.diagram, b"PNG DATA".to_vec());
+        assert_eq!(rez.message_attempts, 3);
+        assert_eq!(rez.image_attempts, 2);
+    }
+
+    #[test]
+    fn client_pauses_on_too_many_requests_test() {
+        let (base_url, _requests) = stand_in_server(vec![
+            (429, b"Too Many Requests".to_vec()),
+            (200, br#"{"img": "?png=mscKTO107", "errors": []}"#.to_vec()),
+            (200, b"PNG DATA".to_vec()),
+        ]);
+        // Own limiter, so other tests are not paused
+        let rate_limiter = Arc::new(RateLimiter::new(None));
+        let client = WsdClient::builder()
+            .base_url(&base_url)
+            .retry_policy(fast_retry_policy(2))
+            .rate_limiter(rate_limiter.clone())
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        let rez = client.get_diagram_with_defaults("A->B: text").unwrap();
+        assert_eq!(rez.diagram, b"PNG DATA".to_vec());
+        assert_eq!(rez.message_attempts, 2);
+        // Without Retry-After header requests are paused for default time
+        assert!(start.elapsed() >= DEFAULT_RATE_LIMIT_PAUSE);
+    }
+
+    #[test]
+    fn client_gives_up_after_max_attempts_test() {
+        let (base_url, _requests) = stand_in_server(vec![
+            (503, b"Service Unavailable".to_vec()),
+            (503, b"Service Unavailable".to_vec()),
+            (200, br#"{"img": "?png=mscKTO107", "errors": []}"#.to_vec()),
+        ]);
+        let client = WsdClient::builder()
+            .base_url(&base_url)
+            .retry_policy(fast_retry_policy(2))
+            .build()
+            .unwrap();
+        match client.get_diagram_with_defaults("A->B: text") {
+            Err(err) => assert_eq!(err.status(), Some(503)),
+            Ok(_) => panic!("Status error expected. Instead got diagram"),
+        }
+    }
+
+    #[test]
+    fn client_does_not_retry_client_errors_test() {
+        let (base_url, requests) = stand_in_server(vec![
+            (404, b"Not Found".to_vec()),
+            (200, br#"{"img": "?png=mscKTO107", "errors": []}"#.to_vec()),
+        ]);
+        let client = WsdClient::builder()
+            .base_url(&base_url)
+            .retry_policy(fast_retry_policy(3))
+            .build()
+            .unwrap();
+        match client.get_diagram_with_defaults("A->B: text") {
+            Err(err) => assert_eq!(err.status(), Some(404)),
+            Ok(_) => panic!("Status error expected. Instead got diagram"),
+        }
+        requests.recv().unwrap();
+        assert!(requests.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn client_retries_async_test() {
+        let (base_url, _requests) = stand_in_server(vec![
+            (0, vec![]),
+            (200, br#"{"img": "?png=mscKTO107", "errors": []}"#.to_vec()),
+            (500, b"Internal Server Error".to_vec()),
+            (200, b"PNG DATA".to_vec()),
+        ]);
+        let client = WsdClient::builder()
+            .base_url(&base_url)
+            .retry_policy(fast_retry_policy(3))
+            .build()
+            .unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let rez = runtime.block_on(client.get_diagram_async("A->B: text", &Default::default())).unwrap();
+        assert_eq!(rez.diagram, b"PNG DATA".to_vec());
+        assert_eq!(rez.message_attempts, 2);
+        assert_eq!(rez.image_attempts, 2);
+    }
+
+    #[test]
+    fn submit_without_download_test() {
+        let (base_url, requests) = stand_in_server(vec![
+            (200, br#"{"img": "?pdf=mscKTO107", "errors": ["Line 3: Deactivate: A was not activated."]}"#.to_vec()),
+            (200, b"PDF DATA".to_vec()),
+        ]);
+        let client = WsdClient::builder()
+            .base_url(&base_url)
+            .build()
+            .unwrap();
+        let ticket = client.submit("A->B: text", &Default::default()).unwrap();
+        assert_eq!(ticket.image_path, "?pdf=mscKTO107");
+        assert_eq!(ticket.url(), format!("{}/index.php?pdf=mscKTO107", base_url));
+        assert_eq!(ticket.actual_format, Format::Pdf);
+        assert_eq!(ticket.errors[0].line_number, 3);
+        requests.recv().unwrap();
+        assert!(requests.recv_timeout(Duration::from_millis(100)).is_err());
+
+        let rez = ticket.download().unwrap();
+        assert_eq!(rez.diagram, b"PDF DATA".to_vec());
+        assert_eq!(rez.errors, ticket.errors);
+        assert!(requests.recv().unwrap().starts_with("GET /index.php?pdf=mscKTO107 HTTP/1.1"));
+    }
+
+    #[test]
+    fn render_to_writer_test() {
+        let (base_url, _requests) = stand_in_server(vec![
+            (200, br#"{"img": "?png=mscKTO107", "errors": []}"#.to_vec()),
+            (503, b"Service Unavailable".to_vec()),
+            (200, vec![7u8; 200 * 1024]),
+        ]);
+        let client = WsdClient::builder()
+            .base_url(&base_url)
+            .retry_policy(fast_retry_policy(2))
+            .build()
+            .unwrap();
+        let mut output = vec![];
+        let rez = client.render_to_writer("A->B: text", &Default::default(), &mut output).unwrap();
+        assert_eq!(rez.bytes_written, 200 * 1024);
+        assert_eq!(output, vec![7u8; 200 * 1024]);
+        assert_eq!(rez.actual_format, Format::Png);
+        assert_eq!(rez.image_attempts, 2);
+    }
+
+    #[test]
+    fn client_cache_test() {
+        let cache_dir = std::env::temp_dir().join(format!("wsdclient-client-cache-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        let (base_url, requests) = stand_in_server(vec![
+            (200, br#"{"img": "?png=mscKTO107", "errors": []}"#.to_vec()),
+            (200, b"PNG DATA".to_vec()),
+        ]);
+        let client = WsdClient::builder()
+            .base_url(&base_url)
+            .cache(CacheOptions::new(&cache_dir))
+            .build()
+            .unwrap();
+        let rez = client.get_diagram_with_defaults("A->B: text").unwrap();
+        assert!(!rez.from_cache);
+
+        let rez = client.get_diagram_with_defaults("A->B: text\n").unwrap();
+        assert!(rez.from_cache);
+        assert_eq!(rez.diagram, b"PNG DATA".to_vec());
+
+        let mut output = vec![];
+        let rez = client.render_to_writer("A->B: text", &Default::default(), &mut output).unwrap();
+        assert!(rez.from_cache);
+        assert_eq!(output, b"PNG DATA".to_vec());
+
+        requests.recv().unwrap();
+        requests.recv().unwrap();
+        assert!(requests.recv_timeout(Duration::from_millis(100)).is_err());
+        let _ = std::fs::remove_dir_all(&cache_dir);
+    }
+
+    #[test]
+    fn client_does_not_cache_downgraded_format_test() {
+        let cache_dir = std::env::temp_dir().join(format!("wsdclient-client-downgrade-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        let (base_url, _requests) = stand_in_server(vec![
+            (200, br#"{"img": "?png=mscKTO107", "errors": []}"#.to_vec()),
+            (200, b"PNG DATA".to_vec()),
+        ]);
+        let client = WsdClient::builder()
+            .base_url(&base_url)
+            .cache(CacheOptions::new(&cache_dir))
+            .build()
+            .unwrap();
+        let parameters = PlotParameters {
+            format: Format::Pdf,
+            ..PlotParameters::default()
+        };
+        let mut output = vec![];
+        let rez = client.render_to_writer("A->B: text", &parameters, &mut output).unwrap();
+        assert_eq!(rez.actual_format, Format::Png);
+        assert!(client.cached_diagram("A->B: text", &parameters).is_none());
+        let _ = std::fs::remove_dir_all(&cache_dir);
+    }
+
+    // Client answering from the queue of responses. Retries are disabled unless policy is given
+    fn in_memory_client(responses: Vec<TransportResponse>, retry_policy: RetryPolicy) -> (WsdClient, Arc<InMemoryTransport>) {
+        let transport = Arc::new(InMemoryTransport::new());
+        for response in responses {
+            transport.push_response(response);
+        }
+        let client = WsdClient::builder()
+            .base_url("http://wsd.test")
+            .retry_policy(retry_policy)
+            .rate_limiter(Arc::new(RateLimiter::new(None)))
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+        (client, transport)
+    }
+
+    fn get_diagram_error(responses: Vec<TransportResponse>) -> WsdError {
+        let (client, _transport) = in_memory_client(responses, RetryPolicy::none());
+        match client.get_diagram_with_defaults("A->B: text") {
+            Err(err) => err,
+            Ok(_) => panic!("error expected. Instead got diagram"),
+        }
+    }
+
+    #[test]
+    fn transport_get_diagram_test() {
+        let (client, transport) = in_memory_client(vec![
+            TransportResponse::new(200, br#"{"img": "?svg=mscKTO107", "errors": ["Line 2: Syntax error."]}"#),
+            TransportResponse::new(200, b"SVG DATA"),
+        ], RetryPolicy::none());
+        let parameters = PlotParameters {
+            format: Format::Svg,
+            api_key: Some("key".into()),
+            ..PlotParameters::default()
+        };
+        let rez = client.get_diagram("A->B: text", &parameters).unwrap();
+        assert_eq!(rez.diagram, b"SVG DATA".to_vec());
+        assert_eq!(rez.actual_format, Format::Svg);
+        assert_eq!(rez.errors, vec![DiagramError::from_wsd_error_str("Line 2: Syntax error.").unwrap()]);
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!((requests[0].method.as_str(), requests[0].url.as_str()), ("POST", "http://wsd.test/index.php"));
+        assert!(requests[0].form.contains(&("format".to_owned(), "svg".to_owned())));
+        assert!(requests[0].form.contains(&("apikey".to_owned(), "key".to_owned())));
+        assert!(format!("{:?}", requests[0]).contains(r#"("apikey", "<redacted>")"#));
+        assert_eq!((requests[1].method.as_str(), requests[1].url.as_str()), ("GET", "http://wsd.test/index.php?svg=mscKTO107"));
+    }
+
+    #[test]
+    fn transport_downgraded_format_test() {
+        let (client, _transport) = in_memory_client(vec![
+            TransportResponse::new(200, br#"{"img": "?png=mscKTO107", "errors": []}"#),
+            TransportResponse::new(200, b"PNG DATA"),
+        ], RetryPolicy::none());
+        let parameters = PlotParameters {
+            format: Format::Pdf,
+            ..PlotParameters::default()
+        };
+        let rez = client.get_diagram("A->B: text", &parameters).unwrap();
+        assert_eq!(rez.actual_format, Format::Png);
+    }
+
+    #[test]
+    fn transport_response_errors_test() {
+        match get_diagram_error(vec![TransportResponse::new(200, b"<html>Maintenance</html>")]) {
+            WsdError::Decode { body, .. } => assert_eq!(body, "<html>Maintenance</html>"),
+            err => panic!("Decode error expected. Instead got: {:?}", err),
+        }
+        match get_diagram_error(vec![TransportResponse::new(200, br#"{"img": "mscKTO107", "errors": []}"#)]) {
+            WsdError::InvalidImageUrl { url } => assert_eq!(url, "mscKTO107"),
+            err => panic!("InvalidImageUrl error expected. Instead got: {:?}", err),
+        }
+        match get_diagram_error(vec![TransportResponse::new(200, br#"{"img": "?gif=mscKTO107", "errors": []}"#)]) {
+            WsdError::UnknownFormat { format, .. } => assert_eq!(format, "gif"),
+            err => panic!("UnknownFormat error expected. Instead got: {:?}", err),
+        }
+        match get_diagram_error(vec![TransportResponse::new(200, br#"{"img": "?png=mscKTO107", "errors": ["Syntax error"]}"#)]) {
+            WsdError::InvalidErrorLine { .. } => {},
+            err => panic!("InvalidErrorLine error expected. Instead got: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn transport_status_errors_test() {
+        match get_diagram_error(vec![TransportResponse::new(400, b"Bad Request")]) {
+            WsdError::Status { url, status, body } => {
+                assert_eq!(url, "http://wsd.test/index.php");
+                assert_eq!(status, 400);
+                assert_eq!(body, "Bad Request");
+            }
+            err => panic!("Status error expected. Instead got: {:?}", err),
+        }
+        let err = get_diagram_error(vec![
+            TransportResponse::new(200, br#"{"img": "?png=mscKTO107", "errors": []}"#),
+            TransportResponse::new(404, b"Not Found"),
+        ]);
+        assert_eq!(err.status(), Some(404));
+
+        let mut too_many_requests = TransportResponse::new(429, b"Too Many Requests");
+        too_many_requests.headers.push(("Retry-After".to_owned(), "0".to_owned()));
+        match get_diagram_error(vec![too_many_requests]) {
+            WsdError::RateLimited { retry_after, .. } => assert_eq!(retry_after, Some(Duration::from_secs(0))),
+            err => panic!("RateLimited error expected. Instead got: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn api_key_is_not_in_errors_test() {
+        let parameters = PlotParameters {
+            api_key: Some("key123".into()),
+            ..PlotParameters::default()
+        };
+        let (client, _transport) = in_memory_client(vec![
+            TransportResponse::new(400, b"Bad request: message=A-%3EB&apikey=key123"),
+            TransportResponse::new(200, b"<html>Invalid apikey: key123</html>"),
+        ], RetryPolicy::none());
+        for _ in 0..2 {
+            let err = client.get_diagram("A->B: text", &parameters).err().unwrap();
+            let message = format!("{} {:?}", err, err);
+            assert!(!message.contains("key123"), "key in error: {}", message);
+            assert!(message.contains("<redacted>"));
+        }
+        assert!(!format!("{:?}", parameters).contains("key123"));
+        assert!(!serde_json::to_string(&parameters).unwrap().contains("key123"));
+    }
+
+    #[test]
+    fn transport_retries_test() {
+        let (client, transport) = in_memory_client(vec![], fast_retry_policy(3));
+        transport.push_error(std::io::ErrorKind::ConnectionReset);
+        transport.push_response(TransportResponse::new(200, br#"{"img": "?png=mscKTO107", "errors": []}"#));
+        transport.push_response(TransportResponse::new(503, b"Service Unavailable"));
+        transport.push_response(TransportResponse::new(200, b"PNG DATA"));
+        let mut output = vec![];
+        let rez = client.render_to_writer("A->B: text", &Default::default(), &mut output).unwrap();
+        assert_eq!(output, b"PNG DATA".to_vec());
+        assert_eq!(rez.message_attempts, 2);
+        assert_eq!(rez.image_attempts, 2);
+    }
+
+    #[test]
+    fn transport_async_test() {
+        let (client, transport) = in_memory_client(vec![
+            TransportResponse::new(200, br#"{"img": "?png=mscKTO107", "errors": []}"#),
+            TransportResponse::new(200, b"PNG DATA"),
+        ], RetryPolicy::none());
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let rez = runtime.block_on(client.get_diagram_async("A->B: text", &Default::default())).unwrap();
+        assert_eq!(rez.diagram, b"PNG DATA".to_vec());
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    // Transport which can send requests only without blocking
+    #[derive(Debug)]
+    struct AsyncOnlyTransport(InMemoryTransport);
+
+    impl Transport for AsyncOnlyTransport {
+        fn post_form(&self, _url: &str, _form: &[(String, String)]) -> Result<TransportResponse, WsdError> {
+            panic!("blocking post_form is called")
+        }
+
+        fn get(&self, _url: &str) -> Result<TransportResponse, WsdError> {
+            panic!("blocking get is called")
+        }
+
+        fn post_form_async(&self, url: &str, form: &[(String, String)]) -> TransportFuture {
+            Box::new(future::result(self.0.post_form(url, form)))
+        }
+
+        fn get_async(&self, url: &str) -> TransportFuture {
+            Box::new(future::result(self.0.get(url)))
+        }
+    }
+
+    #[test]
+    fn transport_async_methods_test() {
+        let inner = InMemoryTransport::new();
+        inner.push_response(TransportResponse::new(200, br#"{"img": "?png=mscKTO107", "errors": []}"#));
+        inner.push_response(TransportResponse::new(200, b"PNG DATA"));
+        let transport = Arc::new(AsyncOnlyTransport(inner));
+        let client = WsdClient::builder()
+            .base_url("http://wsd.test")
+            .rate_limiter(Arc::new(RateLimiter::new(None)))
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let rez = runtime.block_on(client.get_diagram_async("A->B: text", &Default::default())).unwrap();
+        assert_eq!(rez.diagram, b"PNG DATA".to_vec());
+        assert_eq!(transport.0.requests().len(), 2);
+    }
+}
@@ -1,55 +1,467 @@
 use serde::{Serialize, Deserialize};
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches, SubCommand};
 
 use crate::types::{WSDEnum, Format, Style, PaperSize, PaperOrientation, PlotParameters};
+use crate::connection::{ConnectionOptions, Scheme};
+use crate::cache::CacheOptions;
+use crate::batch::BatchOptions;
+use crate::rate_limit::RateLimit;
+use crate::fixtures::Fixtures;
+use crate::secret::Secret;
 
-use std::error::Error;
+use crate::error::WsdError;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command as ProcessCommand, Stdio};
+use std::time::Duration;
+
+/// Environment variable with API key
+pub const API_KEY_ENV: &str = "WEBSEQUENCEDIAGRAM_API_KEY";
+
+/// Name of project config file. It is searched in the directory of the input file and its parents
+pub const PROJECT_CONFIG_FILE_NAME: &str = ".wsdclient.toml";
 
 /// Represent configuration obtained from command line
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Config {
-    /// Name of the input file with diagram specification
-    /// If None - STDIN will be used
-    pub input_file: Option<String>,
+    /// Names of the input files with diagram specification. Globs are already expanded
+    /// If empty - STDIN will be used
+    pub input_files: Vec<String>,
 
-    /// Name of the output file
-    pub output_file: String,
+    /// Name of the output file. Only possible with single input.
+    /// If None - it is derived from input file name. E.g. `foo.wsd` -> `foo.png`
+    pub output_file: Option<String>,
 
     pub plot_parameters: PlotParameters,
 
     /// Should errors be fatal. Like errors in diagram
-    pub is_errors_fatal: bool
+    pub is_errors_fatal: bool,
+
+    /// Should warnings about plot parameters be fatal. See `PlotParameters::validate`
+    pub is_warnings_fatal: bool,
+
+    /// Timeouts, proxy and TLS settings
+    pub connection_options: ConnectionOptions,
+
+    /// On-disk cache of rendered diagrams. None if cache is disabled
+    pub cache: Option<CacheOptions>,
+
+    /// Number of concurrent requests when many input files are given
+    pub batch_options: BatchOptions,
+
+    /// Limit of requests to API. None means no limit
+    pub rate_limit: Option<RateLimit>,
+
+    /// Fixtures to record requests to API into or to replay them from
+    pub fixtures: Option<Fixtures>,
+
+    /// What the program should do
+    pub command: Command,
+
+    /// Where API key in `plot_parameters` was taken from. None if there is no API key
+    pub api_key_source: Option<ApiKeySource>,
+
+    /// How much is logged to STDERR. 0 - only warnings, 1 (`-v`) - requests, statuses,
+    /// timings and sizes, 2 (`-vv`) - also form fields and raw responses
+    pub verbosity: u64,
+}
+
+/// Represent what the program should do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Command {
+    /// Render input files into diagrams
+    Render,
+
+    /// Report where API key is taken from without printing it (`wsdclient auth status`)
+    AuthStatus,
+
+    /// Check which formats API key unlocks by sending minimal diagram (`wsdclient check-key`)
+    CheckKey,
+
+    /// Check input files without sending them to API (`wsdclient lint`)
+    Lint,
+
+    /// Rewrite input files into canonical layout (`wsdclient fmt`).
+    /// If `check` is set, files are only checked (`wsdclient fmt --check`)
+    Fmt { check: bool },
+}
+
+impl Command {
+    /// Is API key used by the command. It is not resolved for other commands,
+    /// so credential helper is not run
+    pub fn uses_api_key(self) -> bool {
+        match self {
+            Command::Render | Command::AuthStatus | Command::CheckKey => true,
+            Command::Lint | Command::Fmt { .. } => false,
+        }
+    }
+}
+
+/// Represent where API key was taken from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ApiKeySource {
+    /// `--api-key` option
+    CommandLine,
+
+    /// File given with `--api-key-file` option
+    File(PathBuf),
+
+    /// Output of credential helper given with `--api-key-command` option
+    /// or `api_key_command` in config file
+    Command(String),
+
+    /// `WEBSEQUENCEDIAGRAM_API_KEY` environment variable
+    Environment,
+
+    /// `api_key` in config file
+    ConfigFile(PathBuf),
+}
+
+impl fmt::Display for ApiKeySource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiKeySource::CommandLine => write!(f, "--api-key option"),
+            ApiKeySource::File(path) => write!(f, "file {}", path.display()),
+            ApiKeySource::Command(command) => write!(f, "command `{}`", command),
+            ApiKeySource::Environment => write!(f, "{} environment variable", API_KEY_ENV),
+            ApiKeySource::ConfigFile(path) => write!(f, "config file {}", path.display()),
+        }
+    }
+}
+
+/// Represent defaults of command line options stored in config file.
+/// Names are the same as names of options with `_` instead of `-`. E.g.
+/// ```toml
+/// style = "napkin"
+/// scale = 200
+/// cache_dir = "/tmp/wsdclient"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Settings {
+    pub style: Option<String>,
+    pub format: Option<String>,
+    pub paper_size: Option<String>,
+    pub paper_orientation: Option<String>,
+    pub scale: Option<u32>,
+    pub errors_fatal: Option<bool>,
+    pub warnings_fatal: Option<bool>,
+
+    /// Timeout in seconds
+    pub connect_timeout: Option<u64>,
+
+    /// Timeout in seconds
+    pub timeout: Option<u64>,
+
+    /// Only allowed in per-user config file
+    pub proxy: Option<String>,
+
+    /// Only allowed in per-user config file
+    pub scheme: Option<String>,
+
+    /// Use cache in user cache directory. Cache is also enabled by `cache_dir`
+    pub cache: Option<bool>,
+
+    /// Only allowed in per-user config file
+    pub cache_dir: Option<String>,
+    pub jobs: Option<usize>,
+    pub rate_limit: Option<f64>,
+    pub rate_burst: Option<u32>,
+}
+
+impl Settings {
+    /// Settings with values missing in `self` taken from `fallback`
+    pub fn or(self, fallback: Settings) -> Settings {
+        Settings {
+            style: self.style.or(fallback.style),
+            format: self.format.or(fallback.format),
+            paper_size: self.paper_size.or(fallback.paper_size),
+            paper_orientation: self.paper_orientation.or(fallback.paper_orientation),
+            scale: self.scale.or(fallback.scale),
+            errors_fatal: self.errors_fatal.or(fallback.errors_fatal),
+            warnings_fatal: self.warnings_fatal.or(fallback.warnings_fatal),
+            connect_timeout: self.connect_timeout.or(fallback.connect_timeout),
+            timeout: self.timeout.or(fallback.timeout),
+            proxy: self.proxy.or(fallback.proxy),
+            scheme: self.scheme.or(fallback.scheme),
+            cache: self.cache.or(fallback.cache),
+            cache_dir: self.cache_dir.or(fallback.cache_dir),
+            jobs: self.jobs.or(fallback.jobs),
+            rate_limit: self.rate_limit.or(fallback.rate_limit),
+            rate_burst: self.rate_burst.or(fallback.rate_burst),
+        }
+    }
+}
+
+/// Represent config file in TOML format. There are two config files:
+/// per-user one (see `ConfigFile::user_path`) and project one (see `ConfigFile::find_project`).
+///
+/// Example:
+/// ```toml
+/// format = "svg"
+/// style = "modern-blue"
+///
+/// [profile.print]
+/// format = "pdf"
+/// paper_size = "a4"
+/// paper_orientation = "landscape"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ConfigFile {
+    /// API key. Config file with the key should not be accessible by other users.
+    /// Only allowed in per-user config file
+    #[serde(default)]
+    pub api_key: Option<Secret>,
+
+    /// Credential helper printing API key to STDOUT. E.g. `pass show websequencediagrams`.
+    /// Only allowed in per-user config file
+    #[serde(default)]
+    pub api_key_command: Option<String>,
+
+    /// Defaults of command line options
+    #[serde(flatten)]
+    pub settings: Settings,
+
+    /// Named sets of defaults selected with `--profile`. E.g. `[profile.print]`
+    #[serde(default)]
+    pub profile: BTreeMap<String, Settings>,
+}
+
+impl ConfigFile {
+    /// Path of per-user config file: `$XDG_CONFIG_HOME/wsdclient/config.toml`
+    /// (usually `~/.config/wsdclient/config.toml`) on Linux and platform config directory on others
+    pub fn user_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("wsdclient").join("config.toml"))
+    }
+
+    /// Read config file. Missing file is the same as empty one
+    pub fn load(path: &Path) -> Result<ConfigFile, WsdError> {
+        if !path.exists() {
+            return Ok(ConfigFile::default());
+        }
+        let config_error = |source: Box<dyn std::error::Error + Send + Sync>| WsdError::Config {
+            path: path.to_owned(),
+            source,
+        };
+        let mut content = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .map_err(|err| config_error(err.into()))?;
+        toml::from_str(&content).map_err(|err| config_error(err.into()))
+    }
+
+    /// Find project config file `.wsdclient.toml` in given directory or its parents
+    pub fn find_project(directory: &Path) -> Option<PathBuf> {
+        directory
+            .ancestors()
+            .map(|dir| dir.join(PROJECT_CONFIG_FILE_NAME))
+            .find(|path| path.is_file())
+    }
+}
+
+/// Represent config files used by the program
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigFiles {
+    /// Per-user config file and its path
+    pub user: Option<(PathBuf, ConfigFile)>,
+
+    /// Project config file and its path
+    pub project: Option<(PathBuf, ConfigFile)>,
 }
 
+impl ConfigFiles {
+    /// Read per-user config file and project config file found from `directory`.
+    /// API key, proxy, scheme and cache directory in project config file are errors: the file is
+    /// usually shared with others, so it should not decide where the API key is sent
+    /// and where files are written
+    pub fn load(directory: &Path) -> Result<ConfigFiles, WsdError> {
+        let user = match ConfigFile::user_path() {
+            Some(path) => {
+                let config_file = ConfigFile::load(&path)?;
+                Some((path, config_file))
+            }
+            None => None,
+        };
+        let project = match ConfigFile::find_project(directory) {
+            // The same file can be both project and per-user one. E.g. in home directory
+            Some(ref path) if user.as_ref().map(|(user_path, _)| user_path) == Some(path) => None,
+            Some(path) => {
+                let config_file = ConfigFile::load(&path)?;
+                check_project_file(&path, &config_file)?;
+                Some((path, config_file))
+            }
+            None => None,
+        };
+        Ok(ConfigFiles { user, project })
+    }
+
+    /// Defaults of command line options. Precedence (first found is used):
+    /// 1. profile section of project config file
+    /// 2. profile section of per-user config file
+    /// 3. project config file
+    /// 4. per-user config file
+    ///
+    /// Profile which is not in any file is an error
+    pub fn settings(&self, profile: Option<&str>) -> Result<Settings, WsdError> {
+        let files = [&self.project, &self.user];
+        let mut settings = Settings::default();
+        if let Some(profile) = profile {
+            let mut found = false;
+            for (_, config_file) in files.iter().filter_map(|file| file.as_ref()) {
+                if let Some(profile_settings) = config_file.profile.get(profile) {
+                    settings = settings.or(profile_settings.clone());
+                    found = true;
+                }
+            }
+            if !found {
+                return Err(WsdError::InvalidArgument {
+                    name: "profile".to_owned(),
+                    value: profile.to_owned(),
+                    expected: format!("It should be [profile.{}] section of {} or per-user config file", profile, PROJECT_CONFIG_FILE_NAME),
+                });
+            }
+        }
+        for (_, config_file) in files.iter().filter_map(|file| file.as_ref()) {
+            settings = settings.or(config_file.settings.clone());
+        }
+        Ok(settings)
+    }
+}
+
+// Finds options which are only allowed in per-user config file, including profile sections
+fn check_project_file(path: &Path, config_file: &ConfigFile) -> Result<(), WsdError> {
+    let mut names = vec![];
+    if config_file.api_key.is_some() {
+        names.push("api_key");
+    }
+    if config_file.api_key_command.is_some() {
+        names.push("api_key_command");
+    }
+    for settings in std::iter::once(&config_file.settings).chain(config_file.profile.values()) {
+        if settings.proxy.is_some() {
+            names.push("proxy");
+        }
+        if settings.scheme.is_some() {
+            names.push("scheme");
+        }
+        if settings.cache_dir.is_some() {
+            names.push("cache_dir");
+        }
+    }
+    names.dedup();
+    if names.is_empty() {
+        return Ok(());
+    }
+    Err(WsdError::Config {
+        path: path.to_owned(),
+        source: format!("{} only allowed in per-user config file", names.join(", ")).into(),
+    })
+}
+
+/// Represent all places API key can be taken from. See `resolve` for precedence
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ApiKeySources {
+    /// Value of `--api-key` option
+    pub api_key: Option<Secret>,
+
+    /// Value of `--api-key-file` option
+    pub api_key_file: Option<PathBuf>,
+
+    /// Value of `--api-key-command` option
+    pub api_key_command: Option<String>,
+
+    /// Value of `WEBSEQUENCEDIAGRAM_API_KEY` environment variable
+    pub environment: Option<Secret>,
+
+    /// Path of config file and its content
+    pub config_file: Option<(PathBuf, ConfigFile)>,
+}
+
+impl ApiKeySources {
+    /// Find API key. The first of the following sources which is set is used:
+    /// 1. `--api-key` option
+    /// 2. `--api-key-file` option. The file should not be accessible by other users
+    /// 3. `--api-key-command` option
+    /// 4. `WEBSEQUENCEDIAGRAM_API_KEY` environment variable
+    /// 5. `api_key_command` in config file
+    /// 6. `api_key` in config file. The file should not be accessible by other users
+    ///
+    /// Explicit options override environment and environment overrides config file.
+    /// Source which is set but cannot be read (e.g. command fails) is an error,
+    /// next sources are not tried.
+    pub fn resolve(&self) -> Result<Option<(Secret, ApiKeySource)>, WsdError> {
+        if let Some(ref api_key) = self.api_key {
+            return Ok(Some((api_key.clone(), ApiKeySource::CommandLine)));
+        }
+        if let Some(ref path) = self.api_key_file {
+            return read_api_key_file(path).map(|api_key| Some((api_key, ApiKeySource::File(path.clone()))));
+        }
+        if let Some(ref command) = self.api_key_command {
+            return run_api_key_command(command).map(|api_key| Some((api_key, ApiKeySource::Command(command.clone()))));
+        }
+        if let Some(ref api_key) = self.environment {
+            return Ok(Some((api_key.clone(), ApiKeySource::Environment)));
+        }
+        if let Some((ref path, ref config_file)) = self.config_file {
+            if let Some(ref command) = config_file.api_key_command {
+                return run_api_key_command(command).map(|api_key| Some((api_key, ApiKeySource::Command(command.clone()))));
+            }
+            if let Some(ref api_key) = config_file.api_key {
+                let source = ApiKeySource::ConfigFile(path.clone());
+                check_private(path, &source)?;
+                return Ok(Some((api_key.clone(), source)));
+            }
+        }
+        Ok(None)
+    }
+}
 
 impl Config {
     // TODO(mkl): add function for exporting app and args
     // TODO(mkl): add function for parsing command line
-    // TODO(mkl): add verbose option. Like write request and response to website
     /// Obtain program configuration from command line
-    pub fn from_command_line() -> Result<Config, Box<Error>> {
+    pub fn from_command_line() -> Result<Config, WsdError> {
         let matches = App::new("wsdclient")
             .version(option_env!("CARGO_PKG_VERSION").unwrap_or("<version unknown>"))
             .author("Mykola Sakhno <mykola.sakhno@bitfury.com>")
             .about("wsdclient is a tool for creating diagrams from their textual representation using websequencediagrams public API. Note: errors are not fatal by default.")
             .arg(
                 Arg::with_name("input-file")
-                    .help("set the input file to use. If not specified STDIN is read.")
+                    .help("set the input files to use. Globs like diagrams/*.wsd are supported. If not specified STDIN is read.")
                     .index(1)
+                    .multiple(true)
             )
             .arg(
                 Arg::with_name("output-file")
-                    .help("Output file for diagram. By default out.<format> is used. E.g. out.png")
+                    .help("Output file for diagram. Only possible with single input file. By default it is derived from input file name, e.g. foo.wsd -> foo.png. For STDIN out.<format> is used. E.g. out.png")
                     .long("output")
                     .short("o")
                     .takes_value(true)
             )
             .arg(
                 Arg::with_name("api-key")
-                    .help("websequencediagram api key. For security reason it is better to use environmental variable WEBSEQUENCEDIAGRAM_API_KEY. Command line option has higher precedence over environment variable. Api key can be obtained by going to http://www.websequencediagrams.com/users/getapikey while logged in.")
+                    .help("websequencediagram api key. For security reason it is better to use --api-key-file, --api-key-command, environmental variable WEBSEQUENCEDIAGRAM_API_KEY or config file. Command line option has the highest precedence. Api key can be obtained by going to http://www.websequencediagrams.com/users/getapikey while logged in.")
                     .long("api-key")
                     .takes_value(true)
+                    .global(true)
+            )
+            .arg(
+                Arg::with_name("api-key-file")
+                    .help("Read websequencediagram api key from the file. The file should not be accessible by other users (chmod 600).")
+                    .long("api-key-file")
+                    .takes_value(true)
+                    .global(true)
+            )
+            .arg(
+                Arg::with_name("api-key-command")
+                    .help("Run the command (credential helper) and use its output as websequencediagram api key. E.g. \"pass show websequencediagrams\". Can also be set with api_key_command in config file.")
+                    .long("api-key-command")
+                    .takes_value(true)
+                    .global(true)
             )
             .arg(
                 Arg::with_name("format")
@@ -86,99 +498,356 @@ impl Config {
                     .help("Treat all errors as fatal. By default some errors: like incorrect lines in diagram are ignored")
                     .long("errors-fatal")
             )
+            .arg(
+                Arg::with_name("warnings-fatal")
+                    .help("Exit without contacting the server if plot parameters have problems: like paper size for png format or pdf format without API key. By default they are reported as warnings")
+                    .long("warnings-fatal")
+            )
+            .arg(
+                Arg::with_name("connect-timeout")
+                    .help("Timeout for establishing connection in seconds. By default there is no timeout.")
+                    .long("connect-timeout")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("timeout")
+                    .help("Timeout for each request in seconds. It includes connecting, sending request and reading response. Default value is 30.")
+                    .long("timeout")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("proxy")
+                    .help("Proxy for all requests. E.g. http://proxy.local:3128. By default proxy is taken from HTTPS_PROXY, HTTP_PROXY or ALL_PROXY environment variables. Hosts from NO_PROXY environment variable are connected directly (ports of its entries are ignored, CIDR entries are not supported).")
+                    .long("proxy")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("scheme")
+                    .help(&format!("Scheme used to connect to websequencediagrams. Possible values: {}. Default value is http.", Scheme::help_str()))
+                    .long("scheme")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("root-certificate")
+                    .help("PEM file with additional trusted root certificate. E.g. certificate of corporate proxy. Can be used multiple times.")
+                    .long("root-certificate")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+            )
+            .arg(
+                Arg::with_name("cache")
+                    .help("Use cache of rendered diagrams in user cache directory, e.g. ~/.cache/wsdclient. Unchanged diagrams are not sent to websequencediagrams again")
+                    .long("cache")
+            )
+            .arg(
+                Arg::with_name("cache-dir")
+                    .help("Use cache of rendered diagrams in given directory")
+                    .long("cache-dir")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("no-cache")
+                    .help("Do not use cache of rendered diagrams even if it is enabled in config file")
+                    .long("no-cache")
+                    .conflicts_with_all(&["cache", "cache-dir"])
+            )
+            .arg(
+                Arg::with_name("jobs")
+                    .help("Number of diagrams rendered at the same time when many input files are given. Default value is 4.")
+                    .long("jobs")
+                    .short("j")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("rate-limit")
+                    .help("Maximum number of requests to websequencediagrams per second. E.g. 0.5 is one request in 2 seconds. Each diagram takes 2 requests. By default there is no limit, but requests are paused when server responds with 429 Too Many Requests.")
+                    .long("rate-limit")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("rate-burst")
+                    .help("Maximum number of requests sent at once when --rate-limit is used. Default value is 1.")
+                    .long("rate-burst")
+                    .takes_value(true)
+                    .requires("rate-limit")
+            )
+            .arg(
+                Arg::with_name("record-fixtures")
+                    .help("Save every request to websequencediagrams and its response into given directory. API key is not saved. Cache of rendered diagrams is not used.")
+                    .long("record-fixtures")
+                    .takes_value(true)
+                    .conflicts_with_all(&["cache", "cache-dir"])
+            )
+            .arg(
+                Arg::with_name("replay-fixtures")
+                    .help("Do not send requests to websequencediagrams. Responses are taken from given directory recorded with --record-fixtures. Unrecorded request is an error. Cache of rendered diagrams is not used.")
+                    .long("replay-fixtures")
+                    .takes_value(true)
+                    .conflicts_with_all(&["record-fixtures", "cache", "cache-dir"])
+            )
+            .arg(
+                Arg::with_name("verbose")
+                    .help("Print requests to websequencediagrams and responses to STDERR. Use -vv to also print form fields and raw responses. API key is never printed.")
+                    .short("v")
+                    .long("verbose")
+                    .multiple(true)
+            )
+            .arg(
+                Arg::with_name("profile")
+                    .help("Use defaults from [profile.<name>] section of config files. Config files are .wsdclient.toml in the directory of input file or its parents and per-user ~/.config/wsdclient/config.toml. Command line options override config files.")
+                    .long("profile")
+                    .takes_value(true)
+                    .global(true)
+            )
+            .subcommand(
+                SubCommand::with_name("auth")
+                    .about("Manage websequencediagram api key")
+                    .subcommand(
+                        SubCommand::with_name("status")
+                            .about("Show where api key is taken from without printing it. Precedence: --api-key, --api-key-file, --api-key-command, WEBSEQUENCEDIAGRAM_API_KEY, api_key_command and api_key in config file")
+                    )
+            )
+            .subcommand(
+                SubCommand::with_name("check-key")
+                    .about("Check whether api key unlocks premium formats. Minimal diagram is sent once for every premium format. Reports available formats and quota information returned by server")
+            )
+            .subcommand(
+                SubCommand::with_name("lint")
+                    .about("Check input files for errors without sending them to server: syntax errors, unclosed blocks, unbalanced activations, unknown participants in notes, duplicate aliases. Messages of syntax errors differ from messages of server. Exits with non-zero code if there are errors")
+                    .arg(
+                        Arg::with_name("input-file")
+                            .help("set the input files to check. Globs like diagrams/*.wsd are supported. If not specified STDIN is read.")
+                            .index(1)
+                            .multiple(true)
+                    )
+            )
+            .subcommand(
+                SubCommand::with_name("fmt")
+                    .about("Rewrite input files into canonical layout: indentation of blocks, spacing around arrows and `:`, empty lines. Comments are kept. STDIN is formatted into STDOUT")
+                    .arg(
+                        Arg::with_name("input-file")
+                            .help("set the input files to format. Globs like diagrams/*.wsd are supported. If not specified STDIN is read.")
+                            .index(1)
+                            .multiple(true)
+                    )
+                    .arg(
+                        Arg::with_name("check")
+                            .help("Do not change files. Exit with non-zero code if some of them are not formatted")
+                            .long("check")
+                    )
+            )
             .get_matches();
 
 
-            let mut api_key: Option<String> = None;
-            if let Some(api_key_arg) = matches.value_of("api-key") {
-                api_key = Some(api_key_arg.to_owned())
-            } else if let Ok(api_key_env) = std::env::var("WEBSEQUENCEDIAGRAM_API_KEY") {
-                api_key = Some(api_key_env);
+            let (command, input_matches) = match matches.subcommand() {
+                ("auth", Some(auth_matches)) => match auth_matches.subcommand_name() {
+                    Some("status") => (Command::AuthStatus, &matches),
+                    _ => return Err(WsdError::InvalidArgument {
+                        name: "auth".to_owned(),
+                        value: auth_matches.subcommand_name().unwrap_or("").to_owned(),
+                        expected: "Possible subcommands are: status".to_owned(),
+                    }),
+                },
+                ("check-key", Some(_)) => (Command::CheckKey, &matches),
+                ("lint", Some(lint_matches)) => (Command::Lint, lint_matches),
+                ("fmt", Some(fmt_matches)) => (Command::Fmt { check: fmt_matches.is_present("check") }, fmt_matches),
+                _ => (Command::Render, &matches),
+            };
+
+            let mut input_files: Vec<String> = vec![];
+            for input_file_arg in input_matches.values_of("input-file").into_iter().flatten() {
+                input_files.extend(expand_glob(input_file_arg)?);
             }
 
+            // Project config file is searched from the first input file. For STDIN from current directory
+            let config_directory = match input_files.first() {
+                Some(input_file) => Path::new(input_file).parent().map(Path::to_owned).unwrap_or_default(),
+                None => PathBuf::new(),
+            };
+            let config_directory = std::env::current_dir()
+                .map(|current_dir| current_dir.join(&config_directory))
+                .unwrap_or(config_directory);
+            let config_files = ConfigFiles::load(&config_directory)?;
+            let settings = config_files.settings(matches.value_of("profile"))?;
+
+            let api_key_sources = ApiKeySources {
+                api_key: matches.value_of("api-key").map(Secret::new),
+                api_key_file: matches.value_of("api-key-file").map(PathBuf::from),
+                api_key_command: matches.value_of("api-key-command").map(|command| command.to_owned()),
+                environment: std::env::var(API_KEY_ENV).ok().map(Secret::new),
+                config_file: config_files.user,
+            };
+            let resolved_api_key = if command.uses_api_key() {
+                api_key_sources.resolve()?
+            } else {
+                None
+            };
+            let (api_key, api_key_source) = match resolved_api_key {
+                Some((api_key, api_key_source)) => (Some(api_key), Some(api_key_source)),
+                None => (None, None),
+            };
+
             let mut format = Format::Png;
-            if let Some(format_arg_str) = matches.value_of("format") {
+            if let Some(format_arg_str) = arg_or_setting(&matches, "format", &settings.format).as_deref() {
                 if let Some(format_arg) = Format::from_str(format_arg_str) {
                     format = format_arg;
                 } else {
-                    let error_msg = format!(
-                        "incorrect format value. Possible values are: {}. Got: {}",
-                        Format::help_str(),
-                        format_arg_str
-                    );
-                    return Err(error_msg.into());
+                    return Err(WsdError::InvalidArgument {
+                        name: "format".to_owned(),
+                        value: format_arg_str.to_owned(),
+                        expected: format!("Possible values are: {}", Format::help_str()),
+                    });
                 }
             }
 
             let mut style = Style::Default;
-            if let Some(style_arg_str) = matches.value_of("style") {
+            if let Some(style_arg_str) = arg_or_setting(&matches, "style", &settings.style).as_deref() {
                 if let Some(style_arg) = Style::from_str(style_arg_str) {
                     style = style_arg;
                 } else {
-                    let error_msg = format!(
-                        "ERROR: incorrect style value. Possible values are: {}. Got: {}",
-                        Style::help_str(),
-                        style_arg_str
-                    );
-                    return Err(error_msg.into());
+                    return Err(WsdError::InvalidArgument {
+                        name: "style".to_owned(),
+                        value: style_arg_str.to_owned(),
+                        expected: format!("Possible values are: {}", Style::help_str()),
+                    });
                 }
             }
 
             let mut paper_size: Option<PaperSize> = None;
-            if let Some(paper_size_arg_str) = matches.value_of("paper-size") {
+            if let Some(paper_size_arg_str) = arg_or_setting(&matches, "paper-size", &settings.paper_size).as_deref() {
                 if let Some(paper_size_arg) = PaperSize::from_str(paper_size_arg_str) {
                     paper_size = Some(paper_size_arg)
                 } else {
-                    let error_msg = format!(
-                        "ERROR: incorrect paper-size value. Possible values are: {}. Got: {}",
-                        PaperSize::help_str(),
-                        paper_size_arg_str
-                    );
-                    return Err(error_msg.into());
+                    return Err(WsdError::InvalidArgument {
+                        name: "paper-size".to_owned(),
+                        value: paper_size_arg_str.to_owned(),
+                        expected: format!("Possible values are: {}", PaperSize::help_str()),
+                    });
                 }
             }
 
             let mut paper_orientation: Option<PaperOrientation> = None;
-            if let Some(paper_orientation_arg_str) = matches.value_of("paper-orientation") {
+            if let Some(paper_orientation_arg_str) = arg_or_setting(&matches, "paper-orientation", &settings.paper_orientation).as_deref() {
                 if let Some(paper_orientation_arg) =
                 PaperOrientation::from_str(paper_orientation_arg_str)
                 {
                     paper_orientation = Some(paper_orientation_arg)
                 } else {
-                    let error_msg = format!("ERROR: incorrect paper-orientation value. Possible values are: {}. Got: {}", PaperOrientation::help_str(), paper_orientation_arg_str);
-                    return Err(error_msg.into());
+                    return Err(WsdError::InvalidArgument {
+                        name: "paper-orientation".to_owned(),
+                        value: paper_orientation_arg_str.to_owned(),
+                        expected: format!("Possible values are: {}", PaperOrientation::help_str()),
+                    });
                 }
             }
 
             let mut scale: Option<u32> = None;
-            if let Some(scale_arg_str) = matches.value_of("scale") {
+            if let Some(scale_arg_str) = arg_or_setting(&matches, "scale", &settings.scale).as_deref() {
                 use std::str::FromStr;
                 if let Ok(scale_arg) = u32::from_str(scale_arg_str) {
                     scale = Some(scale_arg)
                 } else {
-                    let error_msg = format!(
-                        "ERROR: incorrect scale value. It should be positive integer. Got: {}",
-                        scale_arg_str
-                    );
-                    return Err(error_msg.into());
+                    return Err(WsdError::InvalidArgument {
+                        name: "scale".to_owned(),
+                        value: scale_arg_str.to_owned(),
+                        expected: "It should be positive integer".to_owned(),
+                    });
                 }
             }
 
-            let output_file: String =
-                if let Some(output_file_arg) = matches.value_of("output-file") {
-                    output_file_arg.to_owned()
-                } else {
-                    format!("out.{}", format.wsd_value())
-                };
+            let output_file = matches.value_of("output-file").map(|output_file_arg| output_file_arg.to_owned());
+            if let Some(ref output_file) = output_file {
+                if input_files.len() > 1 {
+                    return Err(WsdError::InvalidArgument {
+                        name: "output".to_owned(),
+                        value: output_file.clone(),
+                        expected: format!("It is only possible with single input file. Got {} input files", input_files.len()),
+                    });
+                }
+            }
 
-            let input_file: Option<String> =
-                if let Some(input_file_arg) = matches.value_of("input-file") {
-                    Some(input_file_arg.to_owned())
+            let mut batch_options = BatchOptions::default();
+            if let Some(jobs_arg_str) = arg_or_setting(&matches, "jobs", &settings.jobs).as_deref() {
+                use std::str::FromStr;
+                match usize::from_str(jobs_arg_str) {
+                    Ok(jobs) if jobs > 0 => batch_options.concurrency = jobs,
+                    _ => return Err(WsdError::InvalidArgument {
+                        name: "jobs".to_owned(),
+                        value: jobs_arg_str.to_owned(),
+                        expected: "It should be positive integer".to_owned(),
+                    }),
+                }
+            }
+
+            let is_errors_fatal = matches.occurrences_of("errors-fatal") > 0 || settings.errors_fatal.unwrap_or(false);
+            let is_warnings_fatal = matches.occurrences_of("warnings-fatal") > 0 || settings.warnings_fatal.unwrap_or(false);
+
+            let mut scheme: Option<Scheme> = None;
+            if let Some(scheme_arg_str) = arg_or_setting(&matches, "scheme", &settings.scheme).as_deref() {
+                if let Some(scheme_arg) = Scheme::from_str(scheme_arg_str) {
+                    scheme = Some(scheme_arg);
                 } else {
-                    None
-                };
+                    return Err(WsdError::InvalidArgument {
+                        name: "scheme".to_owned(),
+                        value: scheme_arg_str.to_owned(),
+                        expected: format!("Possible values are: {}", Scheme::help_str()),
+                    });
+                }
+            }
 
-            let is_errors_fatal = matches.occurrences_of("errors-fatal") > 0;
+            let connection_options = ConnectionOptions {
+                connect_timeout: parse_seconds(arg_or_setting(&matches, "connect-timeout", &settings.connect_timeout).as_deref(), "connect-timeout")?,
+                timeout: parse_seconds(arg_or_setting(&matches, "timeout", &settings.timeout).as_deref(), "timeout")?,
+                proxy: arg_or_setting(&matches, "proxy", &settings.proxy).as_deref().map(|proxy| proxy.to_owned()),
+                scheme,
+                root_certificates: matches
+                    .values_of("root-certificate")
+                    .map(|root_certificates| root_certificates.map(PathBuf::from).collect())
+                    .unwrap_or_default(),
+                ..ConnectionOptions::default()
+            };
+
+            let mut rate_limit: Option<RateLimit> = None;
+            if let Some(rate_limit_arg_str) = arg_or_setting(&matches, "rate-limit", &settings.rate_limit).as_deref() {
+                use std::str::FromStr;
+                match f64::from_str(rate_limit_arg_str) {
+                    Ok(requests_per_second) if requests_per_second > 0.0 && requests_per_second.is_finite() =>
+                        rate_limit = Some(RateLimit::per_second(requests_per_second)),
+                    _ => return Err(WsdError::InvalidArgument {
+                        name: "rate-limit".to_owned(),
+                        value: rate_limit_arg_str.to_owned(),
+                        expected: "It should be positive number of requests per second".to_owned(),
+                    }),
+                }
+            }
+            if let (Some(rate_burst_arg_str), Some(ref mut rate_limit)) = (arg_or_setting(&matches, "rate-burst", &settings.rate_burst).as_deref(), rate_limit.as_mut()) {
+                use std::str::FromStr;
+                match u32::from_str(rate_burst_arg_str) {
+                    Ok(burst) if burst > 0 => rate_limit.burst = burst,
+                    _ => return Err(WsdError::InvalidArgument {
+                        name: "rate-burst".to_owned(),
+                        value: rate_burst_arg_str.to_owned(),
+                        expected: "It should be positive integer".to_owned(),
+                    }),
+                }
+            }
+
+            let fixtures = match (matches.value_of("record-fixtures"), matches.value_of("replay-fixtures")) {
+                (Some(record_fixtures_arg), _) => Some(Fixtures::record(record_fixtures_arg)),
+                (None, Some(replay_fixtures_arg)) => Some(Fixtures::replay(replay_fixtures_arg)),
+                (None, None) => None,
+            };
+
+            let cache = if matches.occurrences_of("no-cache") > 0 {
+                None
+            } else if let Some(cache_dir_arg) = arg_or_setting(&matches, "cache-dir", &settings.cache_dir).as_deref() {
+                Some(CacheOptions::new(cache_dir_arg))
+            } else if matches.occurrences_of("cache") > 0 || settings.cache.unwrap_or(false) {
+                CacheOptions::user_default()
+            } else {
+                None
+            };
 
             let plot_parameters = PlotParameters {
                 style,
@@ -189,10 +858,346 @@ impl Config {
                 api_key,
             };
             Ok(Config {
-                input_file,
+                input_files,
                 output_file,
                 plot_parameters,
-                is_errors_fatal
+                is_errors_fatal,
+                is_warnings_fatal,
+                connection_options,
+                cache,
+                batch_options,
+                rate_limit,
+                fixtures,
+                command,
+                api_key_source,
+                verbosity: matches.occurrences_of("verbose"),
             })
         }
+
+    /// Name of the output file for given input file (None for STDIN).
+    /// E.g. `foo.wsd` -> `foo.png`
+    pub fn output_file_for(&self, input_file: Option<&str>) -> String {
+        if let Some(ref output_file) = self.output_file {
+            return output_file.clone();
+        }
+        let extension = self.plot_parameters.format.wsd_value();
+        match input_file {
+            Some(input_file) => Path::new(input_file)
+                .with_extension(extension)
+                .to_string_lossy()
+                .into_owned(),
+            None => format!("out.{}", extension),
+        }
+    }
+}
+
+// Value of the option from command line or, if it is not given, from config files
+fn arg_or_setting<T: ToString>(matches: &ArgMatches, name: &str, setting: &Option<T>) -> Option<String> {
+    matches
+        .value_of(name)
+        .map(|value| value.to_owned())
+        .or_else(|| setting.as_ref().map(ToString::to_string))
+}
+
+// Reads API key from the file. Surrounding whitespace (like trailing newline) is removed
+fn read_api_key_file(path: &Path) -> Result<Secret, WsdError> {
+    let source = ApiKeySource::File(path.to_owned());
+    check_private(path, &source)?;
+    let mut content = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut content))
+        .map_err(|err| api_key_error(&source, &err.to_string()))?;
+    non_empty_api_key(content, &source)
+}
+
+// Runs credential helper with shell and reads API key from its STDOUT
+fn run_api_key_command(command: &str) -> Result<Secret, WsdError> {
+    let source = ApiKeySource::Command(command.to_owned());
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let output = ProcessCommand::new(shell)
+        .arg(flag)
+        .arg(command)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| api_key_error(&source, &err.to_string()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(api_key_error(&source, format!("command failed with {}. {}", output.status, stderr.trim()).trim_end()));
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| api_key_error(&source, "output is not valid UTF-8"))?;
+    non_empty_api_key(stdout, &source)
+}
+
+fn non_empty_api_key(content: String, source: &ApiKeySource) -> Result<Secret, WsdError> {
+    // Content is wrapped first, so it is cleared on drop even if it is not used
+    let content = Secret::new(content);
+    let api_key = content.expose().trim();
+    if api_key.is_empty() {
+        return Err(api_key_error(source, "it is empty"));
+    }
+    Ok(Secret::new(api_key))
+}
+
+// Checks that file with API key cannot be read or written by other users. Like ssh does for keys
+#[cfg(unix)]
+fn check_private(path: &Path, source: &ApiKeySource) -> Result<(), WsdError> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = std::fs::metadata(path)
+        .map_err(|err| api_key_error(source, &err.to_string()))?
+        .permissions()
+        .mode();
+    if mode & 0o077 != 0 {
+        return Err(api_key_error(source, &format!(
+            "file is accessible by other users (mode {:o}). Run: chmod 600 {}",
+            mode & 0o777,
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_private(_path: &Path, _source: &ApiKeySource) -> Result<(), WsdError> {
+    Ok(())
+}
+
+fn api_key_error(source: &ApiKeySource, reason: &str) -> WsdError {
+    WsdError::ApiKey {
+        source: source.to_string(),
+        reason: reason.to_owned(),
+    }
+}
+
+// Expands glob pattern into list of files. Arguments without glob characters are used as is
+fn expand_glob(arg: &str) -> Result<Vec<String>, WsdError> {
+    if !arg.contains(&['*', '?', '['][..]) {
+        return Ok(vec![arg.to_owned()]);
+    }
+    let invalid_glob = |expected: &str| WsdError::InvalidArgument {
+        name: "input-file".to_owned(),
+        value: arg.to_owned(),
+        expected: expected.to_owned(),
+    };
+    let paths = glob::glob(arg)
+        .map_err(|err| invalid_glob(&format!("It should be file name or glob pattern like diagrams/*.wsd. {}", err)))?
+        .filter_map(|path| path.ok())
+        .filter(|path| path.is_file())
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    if paths.is_empty() {
+        return Err(invalid_glob("Glob pattern does not match any file"));
+    }
+    Ok(paths)
+}
+
+// Parses timeout given in seconds
+fn parse_seconds(arg: Option<&str>, name: &str) -> Result<Option<Duration>, WsdError> {
+    use std::str::FromStr;
+    match arg {
+        Some(arg_str) => match u64::from_str(arg_str) {
+            Ok(seconds) => Ok(Some(Duration::from_secs(seconds))),
+            Err(_) => Err(WsdError::InvalidArgument {
+                name: name.to_owned(),
+                value: arg_str.to_owned(),
+                expected: "It should be positive integer number of seconds".to_owned(),
+            }),
+        },
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{check_project_file, ApiKeySource, ApiKeySources, ConfigFile, ConfigFiles, PROJECT_CONFIG_FILE_NAME};
+    use crate::error::WsdError;
+    use crate::secret::Secret;
+
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wsdclient-config-{}-test-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_private(path: &Path, content: &str) {
+        fs::write(path, content).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+    }
+
+    fn resolved(sources: &ApiKeySources) -> Option<(String, ApiKeySource)> {
+        sources
+            .resolve()
+            .unwrap()
+            .map(|(api_key, source)| (api_key.expose().to_owned(), source))
+    }
+
+    #[test]
+    fn api_key_precedence_test() {
+        let dir = temp_dir("precedence");
+        let key_file = dir.join("api_key");
+        write_private(&key_file, "from-file\n");
+        let config_path = dir.join("config.toml");
+        let mut sources = ApiKeySources {
+            api_key: Some(Secret::new("from-option")),
+            api_key_file: Some(key_file.clone()),
+            api_key_command: Some("echo from-option-command".to_owned()),
+            environment: Some(Secret::new("from-env")),
+            config_file: Some((config_path.clone(), ConfigFile {
+                api_key: Some(Secret::new("from-config")),
+                api_key_command: Some("echo from-config-command".to_owned()),
+                ..ConfigFile::default()
+            })),
+        };
+
+        assert_eq!(resolved(&sources), Some(("from-option".to_owned(), ApiKeySource::CommandLine)));
+        sources.api_key = None;
+        assert_eq!(resolved(&sources), Some(("from-file".to_owned(), ApiKeySource::File(key_file))));
+        sources.api_key_file = None;
+        assert_eq!(resolved(&sources), Some(("from-option-command".to_owned(), ApiKeySource::Command("echo from-option-command".to_owned()))));
+        sources.api_key_command = None;
+        assert_eq!(resolved(&sources), Some(("from-env".to_owned(), ApiKeySource::Environment)));
+        sources.environment = None;
+        assert_eq!(resolved(&sources), Some(("from-config-command".to_owned(), ApiKeySource::Command("echo from-config-command".to_owned()))));
+        if let Some((_, ref mut config_file)) = sources.config_file {
+            config_file.api_key_command = None;
+        }
+        write_private(&config_path, "api_key = \"from-config\"");
+        assert_eq!(resolved(&sources), Some(("from-config".to_owned(), ApiKeySource::ConfigFile(config_path))));
+        sources.config_file = None;
+        assert_eq!(resolved(&sources), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn api_key_errors_test() {
+        let dir = temp_dir("errors");
+        let key_file = dir.join("api_key");
+        write_private(&key_file, "  \n");
+        let sources = ApiKeySources {
+            api_key_file: Some(key_file.clone()),
+            ..ApiKeySources::default()
+        };
+        match sources.resolve() {
+            Err(WsdError::ApiKey { reason, .. }) => assert_eq!(reason, "it is empty"),
+            _ => panic!("expected empty key error"),
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::write(&key_file, "secret-key").unwrap();
+            fs::set_permissions(&key_file, fs::Permissions::from_mode(0o644)).unwrap();
+            match sources.resolve() {
+                Err(WsdError::ApiKey { reason, .. }) => assert!(reason.contains("accessible by other users")),
+                _ => panic!("expected permissions error"),
+            }
+        }
+
+        let failing_command = ApiKeySources {
+            // Output differs from the command, which is a part of the error
+            api_key_command: Some("echo $((40 + 2))-key; exit 3".to_owned()),
+            ..ApiKeySources::default()
+        };
+        match failing_command.resolve() {
+            Err(err) => assert!(!err.to_string().contains("42-key")),
+            Ok(_) => panic!("expected command error"),
+        }
+        let _ = fs::remove_dir_all(&dir);
     }
+
+    #[test]
+    fn config_file_test() {
+        let dir = temp_dir("file");
+        let path = dir.join("config.toml");
+        assert_eq!(ConfigFile::load(&path).unwrap(), ConfigFile::default());
+        fs::write(&path, "api_key_command = \"pass show wsd\"\n").unwrap();
+        assert_eq!(ConfigFile::load(&path).unwrap().api_key_command, Some("pass show wsd".to_owned()));
+        fs::write(&path, "api_key = ").unwrap();
+        match ConfigFile::load(&path) {
+            Err(WsdError::Config { path: error_path, .. }) => assert_eq!(error_path, path),
+            _ => panic!("expected config error"),
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn profile_settings_test() {
+        let user: ConfigFile = toml::from_str(r#"
+            format = "svg"
+            scale = 200
+            cache = true
+            [profile.print]
+            format = "pdf"
+            paper_size = "a4"
+        "#).unwrap();
+        let project: ConfigFile = toml::from_str(r#"
+            format = "png"
+            style = "napkin"
+            [profile.print]
+            paper_size = "a3"
+        "#).unwrap();
+        assert_eq!(user.settings.scale, Some(200));
+        let files = ConfigFiles {
+            user: Some((PathBuf::from("user.toml"), user)),
+            project: Some((PathBuf::from(PROJECT_CONFIG_FILE_NAME), project)),
+        };
+
+        let settings = files.settings(None).unwrap();
+        assert_eq!(settings.format.as_deref(), Some("png"));
+        assert_eq!(settings.style.as_deref(), Some("napkin"));
+        assert_eq!(settings.scale, Some(200));
+        assert_eq!(settings.cache, Some(true));
+        assert_eq!(settings.paper_size, None);
+
+        // Profile overrides top-level values of both files
+        let print = files.settings(Some("print")).unwrap();
+        assert_eq!(print.format.as_deref(), Some("pdf"));
+        assert_eq!(print.paper_size.as_deref(), Some("a3"));
+        assert_eq!(print.style.as_deref(), Some("napkin"));
+
+        match files.settings(Some("unknown")) {
+            Err(WsdError::InvalidArgument { name, .. }) => assert_eq!(name, "profile"),
+            _ => panic!("expected unknown profile error"),
+        }
+    }
+
+    #[test]
+    fn project_file_test() {
+        let path = PathBuf::from(PROJECT_CONFIG_FILE_NAME);
+        let error = |content: &str| {
+            let config_file: ConfigFile = toml::from_str(content).unwrap();
+            check_project_file(&path, &config_file).err().map(|err| match err {
+                WsdError::Config { source, .. } => source.to_string(),
+                err => panic!("expected config error, got {:?}", err),
+            })
+        };
+        assert_eq!(error("format = \"svg\"\ncache = true\n[profile.print]\nformat = \"pdf\""), None);
+        assert_eq!(error("api_key = \"secret\"").unwrap(), "api_key only allowed in per-user config file");
+        assert_eq!(error("api_key_command = \"pass show wsd\"").unwrap(), "api_key_command only allowed in per-user config file");
+        // Proxy or scheme from shared file could send API key of the user to another host
+        assert_eq!(error("proxy = \"http://proxy.local:3128\"").unwrap(), "proxy only allowed in per-user config file");
+        assert_eq!(error("scheme = \"http\"\ncache_dir = \"/tmp\"").unwrap(), "scheme, cache_dir only allowed in per-user config file");
+        assert_eq!(error("[profile.a]\nproxy = \"http://a\"\n[profile.b]\nproxy = \"http://b\"").unwrap(), "proxy only allowed in per-user config file");
+    }
+
+    #[test]
+    fn find_project_test() {
+        let dir = temp_dir("project");
+        let nested = dir.join("docs").join("diagrams");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(dir.join(PROJECT_CONFIG_FILE_NAME), "style = \"napkin\"").unwrap();
+        assert_eq!(ConfigFile::find_project(&nested), Some(dir.join(PROJECT_CONFIG_FILE_NAME)));
+
+        fs::write(nested.join(PROJECT_CONFIG_FILE_NAME), "format = \"svg\"").unwrap();
+        assert_eq!(ConfigFile::find_project(&nested), Some(nested.join(PROJECT_CONFIG_FILE_NAME)));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,237 @@
+use serde::{Serialize, Deserialize};
+
+use crate::error::WsdError;
+use crate::types::WSDEnum;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Represent scheme used to connect to API
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scheme {
+    Http,
+    Https,
+}
+
+impl Default for Scheme {
+    fn default() -> Scheme {
+        Scheme::Http
+    }
+}
+
+impl WSDEnum for Scheme {
+    fn premium_feature(&self) -> bool {
+        false
+    }
+
+    fn wsd_value(&self) -> String {
+        match self {
+            Scheme::Http => "http".to_owned(),
+            Scheme::Https => "https".to_owned(),
+        }
+    }
+
+    fn all() -> Vec<Scheme> {
+        vec![Scheme::Http, Scheme::Https]
+    }
+}
+
+/// Represent connection options of the client: timeouts, proxy and TLS settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionOptions {
+    /// Timeout for establishing connection. By default there is no timeout
+    pub connect_timeout: Option<Duration>,
+
+    /// Timeout for each request, from connecting until the whole response is read.
+    /// By default blocking client uses 30 seconds and async client has no timeout
+    pub timeout: Option<Duration>,
+
+    /// Proxy for all requests. E.g. `http://proxy.local:3128`.
+    /// If not set, proxy is taken from `HTTPS_PROXY`, `HTTP_PROXY` or `ALL_PROXY` environment
+    /// variables (depending on scheme). Hosts listed in `NO_PROXY` are always connected directly.
+    /// Port of `NO_PROXY` entry is ignored, CIDR entries like `10.0.0.0/8` are not supported
+    pub proxy: Option<String>,
+
+    /// Do not read proxy settings from environment variables
+    pub ignore_proxy_env: bool,
+
+    /// Scheme used to connect to API. If not set scheme of the base url is used
+    pub scheme: Option<Scheme>,
+
+    /// PEM files with additional trusted root certificates. E.g. certificate of corporate proxy
+    pub root_certificates: Vec<PathBuf>,
+}
+
+impl ConnectionOptions {
+    // Creates proxy for reqwest client. None means direct connection
+    pub(crate) fn reqwest_proxy(&self) -> Result<Option<reqwest::Proxy>, WsdError> {
+        let explicit_proxy = match self.proxy {
+            Some(ref proxy) => Some(parse_proxy_url("proxy", proxy)?),
+            None => None,
+        };
+        let env_proxy = |names: &[&str]| -> Result<Option<reqwest::Url>, WsdError> {
+            if self.ignore_proxy_env {
+                return Ok(None);
+            }
+            match env_var(names) {
+                Some((name, value)) => Ok(Some(parse_proxy_url(&name, &value)?)),
+                None => Ok(None),
+            }
+        };
+        let https_proxy = match explicit_proxy {
+            Some(ref proxy) => Some(proxy.clone()),
+            None => env_proxy(&["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"])?,
+        };
+        let http_proxy = match explicit_proxy {
+            Some(ref proxy) => Some(proxy.clone()),
+            None => env_proxy(&["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"])?,
+        };
+        if https_proxy.is_none() && http_proxy.is_none() {
+            return Ok(None);
+        }
+        let no_proxy = if self.ignore_proxy_env {
+            None
+        } else {
+            env_var(&["NO_PROXY", "no_proxy"]).map(|(_, value)| value)
+        };
+
+        Ok(Some(reqwest::Proxy::custom(move |url| {
+            if let (Some(no_proxy), Some(host)) = (no_proxy.as_ref(), url.host_str()) {
+                if no_proxy_matches(no_proxy, host) {
+                    return None;
+                }
+            }
+            match url.scheme() {
+                "https" => https_proxy.clone(),
+                _ => http_proxy.clone(),
+            }
+        })))
+    }
+
+    // Reads additional root certificates
+    pub(crate) fn reqwest_certificates(&self) -> Result<Vec<reqwest::Certificate>, WsdError> {
+        let mut certificates = vec![];
+        for path in &self.root_certificates {
+            let invalid_certificate = |source: Box<dyn std::error::Error + Send + Sync>| WsdError::InvalidCertificate {
+                path: path.clone(),
+                source,
+            };
+            let mut pem = vec![];
+            File::open(path)
+                .and_then(|mut f| f.read_to_end(&mut pem))
+                .map_err(|err| invalid_certificate(err.into()))?;
+            let certificate = reqwest::Certificate::from_pem(&pem)
+                .map_err(|err| invalid_certificate(err.into()))?;
+            certificates.push(certificate);
+        }
+        Ok(certificates)
+    }
+
+    // Replaces scheme of the url if scheme is set
+    pub(crate) fn apply_scheme(&self, url: &str) -> String {
+        match self.scheme {
+            Some(scheme) => {
+                let rest = match url.find("://") {
+                    Some(pos) => &url[pos + 3..],
+                    None => url,
+                };
+                format!("{}://{}", scheme.wsd_value(), rest)
+            }
+            None => url.to_owned(),
+        }
+    }
+}
+
+fn parse_proxy_url(name: &str, value: &str) -> Result<reqwest::Url, WsdError> {
+    reqwest::Url::parse(value)
+        .map_err(|_| WsdError::InvalidArgument {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            expected: "It should be proxy url like http://proxy.local:3128".to_owned(),
+        })
+}
+
+// Returns name and value of the first non-empty environment variable
+fn env_var(names: &[&str]) -> Option<(String, String)> {
+    names
+        .iter()
+        .filter_map(|name| match std::env::var(name) {
+            Ok(ref value) if !value.trim().is_empty() => Some((name.to_string(), value.trim().to_owned())),
+            _ => None,
+        })
+        .next()
+}
+
+// Checks if host should be connected directly according to NO_PROXY value.
+// NO_PROXY is a comma separated list of hosts. Entry matches host itself and its subdomains.
+// `*` matches all hosts. Port of the entry (`localhost:8080`) is ignored.
+// CIDR entries (`10.0.0.0/8`) are not supported, they match nothing.
+fn no_proxy_matches(no_proxy: &str, host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_lowercase();
+    no_proxy
+        .split(',')
+        .map(|entry| strip_port(entry.trim()).trim_start_matches("*.").trim_start_matches('.').to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            entry == "*" || host == entry || host.ends_with(&format!(".{}", entry))
+        })
+}
+
+// `localhost:8080` -> `localhost`, `[::1]:8080` -> `[::1]`. IPv6 address without brackets is not changed
+fn strip_port(entry: &str) -> &str {
+    let index = match entry.rfind(':') {
+        Some(index) => index,
+        None => return entry,
+    };
+    let host = &entry[..index];
+    let is_port = !entry[index + 1..].is_empty() && entry[index + 1..].chars().all(|c| c.is_ascii_digit());
+    if is_port && (host.ends_with(']') || !host.contains(':')) {
+        host
+    } else {
+        entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connection::{no_proxy_matches, ConnectionOptions, Scheme};
+
+    #[test]
+    fn no_proxy_matches_test() {
+        assert!(no_proxy_matches("*", "www.websequencediagrams.com"));
+        assert!(no_proxy_matches("localhost, websequencediagrams.com", "www.websequencediagrams.com"));
+        assert!(no_proxy_matches(".websequencediagrams.com", "www.websequencediagrams.com"));
+        assert!(no_proxy_matches("127.0.0.1", "127.0.0.1"));
+        assert!(!no_proxy_matches("sequencediagrams.com", "www.websequencediagrams.com"));
+        assert!(!no_proxy_matches("", "localhost"));
+        assert!(no_proxy_matches("localhost:8080", "localhost"));
+        assert!(no_proxy_matches("example.com, .websequencediagrams.com:443", "www.websequencediagrams.com"));
+        assert!(no_proxy_matches("[::1]:8080", "[::1]"));
+        assert!(no_proxy_matches("::1", "::1"));
+        assert!(!no_proxy_matches("localhost:", "localhost"));
+        // CIDR entries are not supported
+        assert!(!no_proxy_matches("10.0.0.0/8", "10.1.2.3"));
+    }
+
+    #[test]
+    fn apply_scheme_test() {
+        let options = ConnectionOptions {
+            scheme: Some(Scheme::Https),
+            ..ConnectionOptions::default()
+        };
+        assert_eq!(options.apply_scheme("http://www.websequencediagrams.com"), "https://www.websequencediagrams.com");
+        assert_eq!(options.apply_scheme("localhost:8080"), "https://localhost:8080");
+        assert_eq!(ConnectionOptions::default().apply_scheme("http://localhost"), "http://localhost");
+    }
+
+    #[test]
+    fn explicit_proxy_validation_test() {
+        let options = ConnectionOptions {
+            proxy: Some("not a url".to_owned()),
+            ..ConnectionOptions::default()
+        };
+        assert!(options.reqwest_proxy().is_err());
+    }
+}
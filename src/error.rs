@@ -0,0 +1,236 @@
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::secret::{Secret, REDACTED};
+use crate::types::WSDEnum;
+
+/// Represent an error returned by this crate
+///
+/// Note: errors in the diagram itself (like `Line 1: Syntax error.`) are not `WsdError`.
+/// They are returned in `WSDResult::errors` as `DiagramError`.
+#[derive(Debug)]
+pub enum WsdError {
+    /// http client cannot be created
+    ClientInit(reqwest::Error),
+
+    /// request cannot be sent or response cannot be received
+    Network {
+        url: String,
+        source: reqwest::Error,
+    },
+
+    /// error reading response body
+    Io {
+        url: String,
+        source: std::io::Error,
+    },
+
+    /// server responded with unsuccessful HTTP status code
+    Status {
+        url: String,
+        status: u16,
+        body: String,
+    },
+
+    /// server responded with 429 Too Many Requests. All requests are paused by rate limiter
+    RateLimited {
+        url: String,
+        retry_after: Option<Duration>,
+        body: String,
+    },
+
+    /// response to the diagram request cannot be deserialized
+    Decode {
+        body: String,
+        source: serde_json::Error,
+    },
+
+    /// url of the diagram returned by API cannot be parsed. Expected url looks like `?png=mscKTO107`
+    InvalidImageUrl {
+        url: String,
+    },
+
+    /// url of the diagram contains unknown format
+    UnknownFormat {
+        url: String,
+        format: String,
+    },
+
+    /// error line returned by API cannot be parsed. Expected line looks like `Line 1: Syntax error.`
+    InvalidErrorLine {
+        line: String,
+        source: Option<std::num::ParseIntError>,
+    },
+
+    /// certificate file cannot be read or parsed
+    InvalidCertificate {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+
+    /// diagram cannot be written to output
+    Output(std::io::Error),
+
+    /// error reading or writing on-disk cache
+    Cache {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    /// fixture cannot be read or written
+    Fixture {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+
+    /// client replays fixtures, but request was not recorded
+    UnrecordedRequest {
+        method: String,
+        path: String,
+        fixture: PathBuf,
+    },
+
+    /// config file cannot be read or parsed
+    Config {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+
+    /// API key cannot be obtained from its source. E.g. file with the key is readable by other users
+    ApiKey {
+        source: String,
+        reason: String,
+    },
+
+    /// incorrect value of option. E.g. in command line
+    InvalidArgument {
+        name: String,
+        value: String,
+        expected: String,
+    },
+}
+
+impl WsdError {
+    /// HTTP status code if server responded with unsuccessful status
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            WsdError::Status { status, .. } => Some(*status),
+            WsdError::RateLimited { .. } => Some(429),
+            _ => None,
+        }
+    }
+
+    // Replaces the secret in texts taken from responses. Server may echo request including API key
+    pub(crate) fn redact(self, secret: &Secret) -> WsdError {
+        let value = secret.expose();
+        if value.is_empty() {
+            return self;
+        }
+        let redact = |text: String| text.replace(value, REDACTED);
+        match self {
+            WsdError::Status { url, status, body } => WsdError::Status {
+                url: redact(url),
+                status,
+                body: redact(body),
+            },
+            WsdError::RateLimited { url, retry_after, body } => WsdError::RateLimited {
+                url: redact(url),
+                retry_after,
+                body: redact(body),
+            },
+            WsdError::Decode { body, source } => WsdError::Decode {
+                body: redact(body),
+                source,
+            },
+            WsdError::InvalidImageUrl { url } => WsdError::InvalidImageUrl {
+                url: redact(url),
+            },
+            WsdError::UnknownFormat { url, format } => WsdError::UnknownFormat {
+                url: redact(url),
+                format: redact(format),
+            },
+            WsdError::InvalidErrorLine { line, source } => WsdError::InvalidErrorLine {
+                line: redact(line),
+                source,
+            },
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for WsdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WsdError::ClientInit(err) => write!(f, "cannot create http client: {}", err),
+            WsdError::Network { url, source } => write!(f, "error sending request to {} : {}", url, source),
+            WsdError::Io { url, source } => write!(f, "error reading response from server {} : {}", url, source),
+            WsdError::Status { url, status, body } => write!(
+                f,
+                "Error response from server: {} HTTP code={} response={}",
+                url, status, body
+            ),
+            WsdError::RateLimited { url, retry_after, body } => match retry_after {
+                Some(retry_after) => write!(
+                    f,
+                    "API rate limit exceeded: {} Retry after {} seconds. response={}",
+                    url, retry_after.as_secs(), body
+                ),
+                None => write!(f, "API rate limit exceeded: {} response={}", url, body),
+            },
+            WsdError::Decode { body, source } => write!(
+                f,
+                "Cannot deserialize websequencegiagram response: {} Response: {}",
+                source, body
+            ),
+            WsdError::InvalidImageUrl { url } => write!(f, "Error parsing diagram url: {}", url),
+            WsdError::UnknownFormat { url, format } => write!(
+                f,
+                "unknown format in diagram url: {}. Known formats are: {}. Got: {}",
+                url,
+                crate::types::Format::help_str(),
+                format
+            ),
+            WsdError::InvalidErrorLine { line, .. } => write!(f, "cannot parse wsd error message: `{}`", line),
+            WsdError::InvalidCertificate { path, source } => write!(
+                f,
+                "cannot load certificate from {} : {}",
+                path.display(), source
+            ),
+            WsdError::Output(err) => write!(f, "error writing diagram: {}", err),
+            WsdError::Cache { path, source } => write!(f, "cache error: {} : {}", path.display(), source),
+            WsdError::Fixture { path, source } => write!(f, "fixture error: {} : {}", path.display(), source),
+            WsdError::UnrecordedRequest { method, path, fixture } => write!(
+                f,
+                "request {} {} was not recorded. Expected fixture: {}",
+                method, path, fixture.display()
+            ),
+            WsdError::Config { path, source } => write!(f, "config file error: {} : {}", path.display(), source),
+            WsdError::ApiKey { source, reason } => write!(f, "cannot read API key from {}: {}", source, reason),
+            WsdError::InvalidArgument { name, value, expected } => write!(
+                f,
+                "incorrect {} value. {}. Got: {}",
+                name, expected, value
+            ),
+        }
+    }
+}
+
+impl Error for WsdError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WsdError::ClientInit(err) => Some(err),
+            WsdError::Network { source, .. } => Some(source),
+            WsdError::Io { source, .. } => Some(source),
+            WsdError::Decode { source, .. } => Some(source),
+            WsdError::InvalidErrorLine { source: Some(source), .. } => Some(source),
+            WsdError::InvalidCertificate { source, .. } => Some(&**source),
+            WsdError::Output(err) => Some(err),
+            WsdError::Cache { source, .. } => Some(source),
+            WsdError::Fixture { source, .. } => Some(&**source),
+            WsdError::Config { source, .. } => Some(&**source),
+            _ => None,
+        }
+    }
+}
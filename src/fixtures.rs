@@ -0,0 +1,301 @@
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::WsdError;
+use crate::transport::{Transport, TransportResponse};
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Represent what client does with fixtures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FixtureMode {
+    /// Requests are sent to API. Every request and its response are saved to fixtures directory
+    Record,
+
+    /// Responses are taken from fixtures directory. No requests are sent.
+    /// Request which was not recorded fails with `WsdError::UnrecordedRequest`
+    Replay,
+}
+
+/// Represent directory with recorded requests to API and responses.
+///
+/// Request is identified by method, path relative to API address and form fields,
+/// so fixtures recorded against one server can be replayed with any base url.
+/// API key is never saved and is not a part of request identity.
+///
+/// Each request is stored in two files: `<name>.json` with request, status and headers
+/// and `<name>.body` with raw body of the response (JSON or image).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fixtures {
+    /// Directory with fixtures
+    pub directory: PathBuf,
+
+    pub mode: FixtureMode,
+}
+
+/// Represent request to API as it is stored in fixture
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixtureRequest {
+    /// HTTP method. E.g. "POST"
+    pub method: String,
+
+    /// Path with query relative to API address. E.g. "/index.php?png=mscKTO107"
+    pub path: String,
+
+    /// Form fields without API key
+    pub form: Vec<(String, String)>,
+}
+
+// Content of `<name>.json`
+#[derive(Debug, Serialize, Deserialize)]
+struct FixtureMeta {
+    request: FixtureRequest,
+    status: u16,
+    headers: Vec<(String, String)>,
+}
+
+// Headers saved in fixtures. Others (like Date) make fixtures non-deterministic
+const SAVED_HEADERS: &[&str] = &["content-type", "retry-after"];
+
+impl FixtureRequest {
+    /// Create request. `url` is absolute url of the request and `base_url` is API address.
+    /// API key is removed from form fields
+    pub fn new(method: &str, base_url: &str, url: &str, form: &[(String, String)]) -> FixtureRequest {
+        let path = if url.starts_with(base_url) { &url[base_url.len()..] } else { url };
+        FixtureRequest {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            form: form
+                .iter()
+                .filter(|(name, _)| name != "apikey")
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Name of the fixture files. E.g. `post-index.php-3f2a9c41d0e5b7a8`
+    pub fn name(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.input(self.method.as_bytes());
+        hasher.input(b"\0");
+        hasher.input(self.path.as_bytes());
+        for (name, value) in &self.form {
+            hasher.input(b"\0");
+            hasher.input(name.as_bytes());
+            hasher.input(b"=");
+            hasher.input(value.as_bytes());
+        }
+        let hash = format!("{:x}", hasher.result());
+        let file = self.path
+            .trim_start_matches('/')
+            .split(&['?', '/'][..])
+            .next()
+            .filter(|file| !file.is_empty())
+            .unwrap_or("root");
+        format!("{}-{}-{}", self.method.to_lowercase(), file, &hash[..16])
+    }
+}
+
+impl Fixtures {
+    pub fn new<P: Into<PathBuf>>(directory: P, mode: FixtureMode) -> Fixtures {
+        Fixtures {
+            directory: directory.into(),
+            mode,
+        }
+    }
+
+    /// Fixtures in record mode
+    pub fn record<P: Into<PathBuf>>(directory: P) -> Fixtures {
+        Fixtures::new(directory, FixtureMode::Record)
+    }
+
+    /// Fixtures in replay mode
+    pub fn replay<P: Into<PathBuf>>(directory: P) -> Fixtures {
+        Fixtures::new(directory, FixtureMode::Replay)
+    }
+
+    /// Get recorded response on the request. Only headers which affect the client
+    /// (`Content-Type` and `Retry-After`) are recorded
+    pub fn load(&self, request: &FixtureRequest) -> Result<TransportResponse, WsdError> {
+        let name = request.name();
+        let meta_path = self.directory.join(format!("{}.json", name));
+        if !meta_path.exists() {
+            return Err(WsdError::UnrecordedRequest {
+                method: request.method.clone(),
+                path: request.path.clone(),
+                fixture: meta_path,
+            });
+        }
+        let meta: FixtureMeta = serde_json::from_slice(&read_file(&meta_path)?)
+            .map_err(|err| fixture_error(&meta_path, err.into()))?;
+        let body = read_file(&self.directory.join(format!("{}.body", name)))?;
+        Ok(TransportResponse {
+            status: meta.status,
+            headers: meta.headers,
+            body,
+        })
+    }
+
+    /// Save request and response
+    pub fn save(&self, request: &FixtureRequest, response: &TransportResponse) -> Result<(), WsdError> {
+        fs::create_dir_all(&self.directory)
+            .map_err(|err| fixture_error(&self.directory, err.into()))?;
+        let name = request.name();
+        let meta = FixtureMeta {
+            request: request.clone(),
+            status: response.status,
+            headers: response.headers
+                .iter()
+                .filter(|(name, _)| SAVED_HEADERS.contains(&name.to_lowercase().as_str()))
+                .cloned()
+                .collect(),
+        };
+        let meta_json = serde_json::to_vec_pretty(&meta)
+            .map_err(|err| fixture_error(&self.directory, err.into()))?;
+        // Body is written first, so fixture without body is never visible
+        write_file(&self.directory.join(format!("{}.body", name)), &response.body)?;
+        write_file(&self.directory.join(format!("{}.json", name)), &meta_json)
+    }
+}
+
+/// Transport which replays responses from fixtures or sends requests with inner transport
+/// and records them
+#[derive(Debug)]
+pub(crate) struct FixtureTransport {
+    inner: Arc<dyn Transport>,
+    fixtures: Fixtures,
+    base_url: String,
+}
+
+impl FixtureTransport {
+    pub(crate) fn new(inner: Arc<dyn Transport>, fixtures: Fixtures, base_url: &str) -> FixtureTransport {
+        FixtureTransport {
+            inner,
+            fixtures,
+            base_url: base_url.to_owned(),
+        }
+    }
+
+    fn respond<F>(&self, request: FixtureRequest, send: F) -> Result<TransportResponse, WsdError>
+        where
+            F: FnOnce(&dyn Transport) -> Result<TransportResponse, WsdError>,
+    {
+        if self.fixtures.mode == FixtureMode::Replay {
+            return self.fixtures.load(&request);
+        }
+        let response = send(&*self.inner)?;
+        self.fixtures.save(&request, &response)?;
+        Ok(response)
+    }
+}
+
+impl Transport for FixtureTransport {
+    fn post_form(&self, url: &str, form: &[(String, String)]) -> Result<TransportResponse, WsdError> {
+        let request = FixtureRequest::new("POST", &self.base_url, url, form);
+        self.respond(request, |inner| inner.post_form(url, form))
+    }
+
+    fn get(&self, url: &str) -> Result<TransportResponse, WsdError> {
+        let request = FixtureRequest::new("GET", &self.base_url, url, &[]);
+        self.respond(request, |inner| inner.get(url))
+    }
+}
+
+fn fixture_error(path: &Path, source: Box<dyn std::error::Error + Send + Sync>) -> WsdError {
+    WsdError::Fixture {
+        path: path.to_owned(),
+        source,
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, WsdError> {
+    let mut data = vec![];
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|err| fixture_error(path, err.into()))?;
+    Ok(data)
+}
+
+fn write_file(path: &Path, data: &[u8]) -> Result<(), WsdError> {
+    File::create(path)
+        .and_then(|mut f| f.write_all(data))
+        .map_err(|err| fixture_error(path, err.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::{FixtureRequest, FixtureTransport, Fixtures};
+    use crate::error::WsdError;
+    use crate::transport::{InMemoryTransport, Transport, TransportResponse};
+
+    use std::sync::Arc;
+
+    fn form(api_key: &str) -> Vec<(String, String)> {
+        vec![
+            ("message".to_owned(), "A->B: text".to_owned()),
+            ("apikey".to_owned(), api_key.to_owned()),
+        ]
+    }
+
+    #[test]
+    fn request_name_test() {
+        let request = FixtureRequest::new("POST", "http://localhost:8080", "http://localhost:8080/index.php", &form("key1"));
+        assert_eq!(request.path, "/index.php");
+        assert_eq!(request.form, vec![("message".to_owned(), "A->B: text".to_owned())]);
+        assert!(request.name().starts_with("post-index.php-"));
+        // API key does not affect the name, base url too
+        assert_eq!(
+            request.name(),
+            FixtureRequest::new("POST", "http://www.websequencediagrams.com", "http://www.websequencediagrams.com/index.php", &form("key2")).name()
+        );
+        let image = FixtureRequest::new("GET", "http://localhost", "http://localhost/index.php?png=mscKTO107", &[]);
+        assert_ne!(image.name(), FixtureRequest::new("GET", "http://localhost", "http://localhost/index.php?png=mscKTO108", &[]).name());
+    }
+
+    #[test]
+    fn save_load_test() {
+        let dir = std::env::temp_dir().join(format!("wsdclient-fixtures-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let request = FixtureRequest::new("POST", "http://localhost", "http://localhost/index.php", &form("secret"));
+        let response = TransportResponse {
+            status: 200,
+            headers: vec![
+                ("content-type".to_owned(), "application/json".to_owned()),
+                ("date".to_owned(), "Wed, 21 Oct 2015 07:28:00 GMT".to_owned()),
+            ],
+            body: br#"{"img": "?png=mscKTO107", "errors": []}"#.to_vec(),
+        };
+
+        match Fixtures::replay(&dir).load(&request) {
+            Err(WsdError::UnrecordedRequest { method, path, .. }) => assert_eq!((method.as_str(), path.as_str()), ("POST", "/index.php")),
+            _ => panic!("expected unrecorded request"),
+        }
+        Fixtures::record(&dir).save(&request, &response).unwrap();
+        let loaded = Fixtures::replay(&dir).load(&request).unwrap();
+        assert_eq!(loaded.body, response.body);
+        assert_eq!(loaded.headers, vec![("content-type".to_owned(), "application/json".to_owned())]);
+        let meta = std::fs::read_to_string(dir.join(format!("{}.json", request.name()))).unwrap();
+        assert!(!meta.contains("secret"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn fixture_transport_test() {
+        let dir = std::env::temp_dir().join(format!("wsdclient-fixture-transport-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let inner = Arc::new(InMemoryTransport::new());
+        inner.push_response(TransportResponse::new(200, b"PNG DATA"));
+        let url = "http://localhost/index.php?png=mscKTO107";
+
+        let recording = FixtureTransport::new(inner.clone(), Fixtures::record(&dir), "http://localhost");
+        assert_eq!(recording.get(url).unwrap().body, b"PNG DATA".to_vec());
+        // Replay does not use inner transport, so empty queue is not an error
+        let replaying = FixtureTransport::new(inner.clone(), Fixtures::replay(&dir), "http://localhost");
+        assert_eq!(replaying.get(url).unwrap().body, b"PNG DATA".to_vec());
+        assert_eq!(inner.requests().len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,163 @@
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+use crate::client::WsdClient;
+use crate::error::WsdError;
+use crate::secret::Secret;
+use crate::types::{Format, PlotParameters, WSDEnum};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Diagram sent to API to check the key. It is as small as possible
+pub const PROBE_SPEC: &str = "A->B:";
+
+/// Represent result of checking API key (see `WsdClient::check_key`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyCheck {
+    /// Key unlocks at least one premium format
+    pub premium: bool,
+
+    /// Formats which can be obtained with the key. Non-premium formats are always available
+    pub available_formats: Vec<Format>,
+
+    /// Fields of API responses besides diagram url, errors and pages. API has no documented
+    /// quota fields, so all of them are kept as is. Empty if server returned nothing else
+    pub quota: serde_json::Map<String, serde_json::Value>,
+
+    /// Result was checked earlier in this process. No requests were made
+    pub from_cache: bool,
+}
+
+lazy_static! {
+    // Results of checks made by this process. Keys are hashes of base url and API key
+    static ref CHECKED_KEYS: Mutex<HashMap<String, KeyCheck>> = Mutex::new(HashMap::new());
+}
+
+impl WsdClient {
+    /// Check which formats are unlocked by `api_key`.
+    ///
+    /// API does not report validity of the key, so `PROBE_SPEC` is sent once for every premium
+    /// format and actual format of the result is compared with requested one.
+    /// Diagrams are not downloaded. Successful result is remembered for the rest of the process,
+    /// so repeated checks of the same key against the same API do not make requests.
+    pub fn check_key(&self, api_key: &Secret) -> Result<KeyCheck, WsdError> {
+        let cache_key = session_key(self.base_url(), api_key);
+        if let Some(checked) = CHECKED_KEYS.lock().unwrap_or_else(|err| err.into_inner()).get(&cache_key) {
+            debug!("API key check for {} is taken from session cache", self.base_url());
+            return Ok(KeyCheck { from_cache: true, ..checked.clone() });
+        }
+
+        let mut available_formats = vec![];
+        let mut quota = serde_json::Map::new();
+        for format in Format::all() {
+            if !format.premium_feature() {
+                available_formats.push(format);
+                continue;
+            }
+            let parameters = PlotParameters {
+                format: format.clone(),
+                api_key: Some(api_key.clone()),
+                ..PlotParameters::default()
+            };
+            let ticket = self.submit(PROBE_SPEC, &parameters)?;
+            debug!("API key check: requested {}, got {}", format.wsd_value(), ticket.actual_format.wsd_value());
+            quota.extend(ticket.response.other);
+            if ticket.actual_format == format {
+                available_formats.push(format);
+            }
+        }
+
+        let checked = KeyCheck {
+            premium: available_formats.iter().any(WSDEnum::premium_feature),
+            available_formats,
+            quota,
+            from_cache: false,
+        };
+        CHECKED_KEYS.lock().unwrap_or_else(|err| err.into_inner()).insert(cache_key, checked.clone());
+        Ok(checked)
+    }
+}
+
+/// Check API key using public websequencediagrams API.
+///
+/// It is a shortcut for `WsdClient::new().check_key(api_key)`.
+pub fn check_key(api_key: &Secret) -> Result<KeyCheck, WsdError> {
+    WsdClient::builder()
+        .build()?
+        .check_key(api_key)
+}
+
+// The key itself is not kept in memory longer than needed
+fn session_key(base_url: &str, api_key: &Secret) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(base_url.as_bytes());
+    hasher.input(b"\0");
+    hasher.input(api_key.expose().as_bytes());
+    format!("{:x}", hasher.result())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::WsdClient;
+    use crate::secret::Secret;
+    use crate::transport::{InMemoryTransport, TransportResponse};
+    use crate::types::Format;
+
+    use std::sync::Arc;
+
+    #[test]
+    fn check_key_test() {
+        let transport = Arc::new(InMemoryTransport::new());
+        transport.push_response(TransportResponse::new(200, br#"{"img": "?pdf=mscKTO107", "errors": [], "quota": 100}"#));
+        transport.push_response(TransportResponse::new(200, br#"{"img": "?png=mscKTO108", "errors": []}"#));
+        transport.push_response(TransportResponse::new(200, br#"{"img": "?png=mscKTO109", "errors": []}"#));
+        transport.push_response(TransportResponse::new(200, br#"{"img": "?png=mscKTO110", "errors": []}"#));
+        let client = WsdClient::builder()
+            .base_url("http://check-key.test")
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+
+        let checked = client.check_key(&Secret::new("pdf-key")).unwrap();
+        assert!(checked.premium);
+        assert_eq!(checked.available_formats, vec![Format::Png, Format::Pdf]);
+        assert_eq!(checked.quota.get("quota"), Some(&serde_json::json!(100)));
+        assert!(!checked.from_cache);
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].form.contains(&("format".to_owned(), "pdf".to_owned())));
+        assert!(requests[1].form.contains(&("format".to_owned(), "svg".to_owned())));
+        assert!(requests[1].form.contains(&("apikey".to_owned(), "pdf-key".to_owned())));
+
+        let cached = client.check_key(&Secret::new("pdf-key")).unwrap();
+        assert!(cached.from_cache);
+        assert_eq!(cached.available_formats, checked.available_formats);
+        assert_eq!(transport.requests().len(), 2);
+
+        let invalid = client.check_key(&Secret::new("invalid-key")).unwrap();
+        assert!(!invalid.premium);
+        assert_eq!(invalid.available_formats, vec![Format::Png]);
+        assert!(invalid.quota.is_empty());
+        assert_eq!(transport.requests().len(), 4);
+    }
+
+    #[test]
+    fn check_key_error_is_not_cached_test() {
+        let transport = Arc::new(InMemoryTransport::new());
+        transport.push_response(TransportResponse::new(400, b"bad request"));
+        transport.push_response(TransportResponse::new(200, br#"{"img": "?pdf=mscKTO107", "errors": []}"#));
+        transport.push_response(TransportResponse::new(200, br#"{"img": "?svg=mscKTO108", "errors": []}"#));
+        let client = WsdClient::builder()
+            .base_url("http://check-key-error.test")
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+
+        assert!(client.check_key(&Secret::new("key")).is_err());
+        let checked = client.check_key(&Secret::new("key")).unwrap();
+        assert_eq!(checked.available_formats, vec![Format::Png, Format::Pdf, Format::Svg]);
+        assert!(!checked.from_cache);
+    }
+}
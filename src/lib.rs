@@ -1,6 +1,3 @@
-#![feature(custom_attribute)]
-#![feature(plugin)]
-
 //! Crate for working with [https://www.websequencediagrams.com/](https://www.websequencediagrams.com/)
 //! public [RESTful API](https://www.websequencediagrams.com/embedding.html)
 //! This service allows to create sequence diagrams from simple text.
@@ -11,12 +8,68 @@
 //! * multiple output formats: png, pdf (premium), svg (premium)
 //! * detection of actual output format. E.g. trying to get pdf with wrong API key leads to png output
 //! * allows specification of scale, paper size, paper orientation, style
-//! * parse returned errors
+//! * check of plot parameters before sending request: premium format without API key, paper options for non-pdf formats, ... (`PlotParameters::validate`)
+//! * parse returned errors and full API response (pages, quota, unknown fields)
+//! * offline parser of diagram specification into syntax tree with source spans (`syntax::parse`)
+//! * offline check of diagrams: syntax errors, unclosed blocks, unbalanced activations, unknown participants (`lint`)
+//! * formatter of diagrams into canonical layout (`syntax::format`)
+//! * builder of diagrams with escaping of names and texts (`Diagram::new().message("A", "B", "text")`)
+//! * diagrams checked at compile time (`wsd!`, requires `macros` feature)
+//! * reusable client with configurable API address (`WsdClient`)
+//! * async API (`get_diagram_async`) built on futures and non-blocking http client
+//! * two-phase API: obtain url of the diagram (`WsdClient::submit`) and download it later
+//! * streaming of the diagram into any `std::io::Write` (`WsdClient::render_to_writer`)
+//! * batch rendering of many diagrams with bounded concurrency (`WsdClient::render_batch`)
+//! * on-disk cache of rendered diagrams with size limit and expiration (`DiskCache`)
+//! * rate limiter shared by all clients in the process, pausing on 429 Too Many Requests (`RateLimiter`)
+//! * retries of failed requests with exponential backoff (`RetryPolicy`)
+//! * record and replay of requests to API for deterministic offline runs (`Fixtures`)
+//! * mock of websequencediagrams API for offline tests (`mock_server`, requires `mock-server` feature)
+//! * pluggable HTTP layer (`Transport`) with in-memory implementation for unit tests (`InMemoryTransport`)
+//! * logging of requests and responses through `log` crate (API key is never logged)
+//! * check which formats API key unlocks and quota reported by API (`WsdClient::check_key`)
+//! * API key is never shown in debug output, errors or serialized parameters (`Secret`)
+//! * timeouts, proxy (including `HTTPS_PROXY`/`NO_PROXY` environment variables), https and custom root certificates
 //!
 //! This crate contains command line tool for accessing websequencediagram API
 //!
 //! `$ wsdclient my_diag.wsd -o my.png`
 //!
+//! Many diagrams can be rendered at once. Output names are derived from input names (`foo.wsd` -> `foo.png`)
+//!
+//! `$ wsdclient diagrams/*.wsd --jobs 8`
+//!
+//! Diagrams can be checked without sending them to the server (see `lint::lint`).
+//! Exit code is non-zero if there are errors:
+//!
+//! `$ wsdclient lint diagrams/*.wsd`
+//!
+//! Diagrams can be rewritten into canonical layout (see `syntax::format`).
+//! With `--check` files are not changed, exit code is non-zero if some of them are not formatted:
+//!
+//! `$ wsdclient fmt --check diagrams/*.wsd`
+//!
+//! Diagrams embedded in Rust code can be checked at compile time with `wsd!` macro
+//! (requires `macros` feature). Broken diagram fails the build.
+//!
+//! API key is taken from `--api-key`, `--api-key-file`, `--api-key-command` (credential helper),
+//! `WEBSEQUENCEDIAGRAM_API_KEY` or per-user config file (see `config::ApiKeySources::resolve`).
+//! Check which one is used without printing the key:
+//!
+//! `$ wsdclient auth status`
+//!
+//! Check whether the key unlocks premium formats (see `WsdClient::check_key`):
+//!
+//! `$ wsdclient check-key`
+//!
+//! Defaults of options can be stored in `.wsdclient.toml` (searched from the directory of input file up)
+//! and in per-user config file. Sections like `[profile.print]` are selected with `--profile print`.
+//! Command line options override config files (see `config::ConfigFiles::settings`).
+//! API key, proxy, scheme and cache directory are only allowed in per-user config file.
+//!
+//! Plot parameters are checked before contacting the server (see `types::PlotParameters::validate`).
+//! Problems are reported as warnings, `--warnings-fatal` makes them fatal.
+//!
 //! Example:
 //! ```
 //! use wsdclient::{get_diagram};
@@ -37,6 +90,19 @@ extern crate clap;
 extern crate serde;
 extern crate serde_json;
 extern crate reqwest;
+extern crate futures;
+extern crate tokio_timer;
+extern crate rand;
+extern crate sha2;
+extern crate dirs;
+extern crate glob;
+extern crate toml;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate log;
+#[cfg(feature = "macros")]
+extern crate wsdclient_macros;
 
 /// Contains types representing plot parameters(like format, page size, ...)
 pub mod types;
@@ -44,8 +110,65 @@ pub mod types;
 /// Contains actual client
 pub mod client;
 
+/// Contains error type of the crate
+pub mod error;
+
+/// Contains policy of retrying failed requests
+pub mod retry;
+
+/// Contains rate limiter of requests to API
+pub mod rate_limit;
+
+/// Contains HTTP transport used by the client
+pub mod transport;
+
+/// Contains wrapper for secret values like API key
+pub mod secret;
+
+/// Contains connection options of the client (timeouts, proxy, TLS)
+pub mod connection;
+
+/// Contains on-disk cache of rendered diagrams
+pub mod cache;
+
+/// Contains batch rendering of many diagrams
+pub mod batch;
+
+/// Contains parser of diagram specification into syntax tree (`wsdclient-syntax` crate)
+pub use wsdclient_syntax as syntax;
+
+/// Contains builder of diagram specification (methods of `syntax::Diagram`)
+pub use wsdclient_syntax::builder;
+
+/// Contains offline check of diagram specification
+pub mod lint;
+
+/// Contains check of API key: which formats it unlocks
+pub mod key_check;
+
+/// Contains recording and replaying of requests to API
+pub mod fixtures;
+
 /// Contains command line parsing
 pub mod config;
 
+/// Contains in-process mock of websequencediagrams API for tests
+#[cfg(any(test, feature = "mock-server"))]
+pub mod mock_server;
+
 pub use types::{Format, Style, PaperSize, PaperOrientation, PlotParameters, WSDEnum, DiagramError};
-pub use client::{get_diagram, WSDResult};
\ No newline at end of file
+pub use error::WsdError;
+pub use retry::RetryPolicy;
+pub use rate_limit::{RateLimit, RateLimiter};
+pub use connection::{ConnectionOptions, Scheme};
+pub use secret::Secret;
+pub use transport::{InMemoryTransport, ReqwestTransport, Transport, TransportFuture, TransportResponse};
+pub use cache::{CacheOptions, DiskCache};
+pub use batch::{BatchOptions, BatchResult};
+pub use fixtures::{FixtureMode, Fixtures};
+pub use key_check::KeyCheck;
+pub use lint::lint;
+pub use syntax::Diagram;
+#[cfg(feature = "macros")]
+pub use wsdclient_macros::wsd;
+pub use client::{get_diagram, get_diagram_async, WSDResult, WebSequenceDiagramResponse, RenderTicket, WSDStreamResult, WsdClient, WsdClientBuilder};
\ No newline at end of file
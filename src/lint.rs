@@ -0,0 +1,58 @@
+use crate::syntax::{self, SyntaxError};
+use crate::types::DiagramError;
+
+/// Check diagram specification without sending it to API.
+///
+/// It finds syntax errors, unclosed blocks, unbalanced activations and deactivations,
+/// unknown participants in notes and duplicate aliases (see `syntax::Diagram::check`).
+/// Errors are returned in the same form as errors from API and sorted by line.
+/// Unlike errors from API, line numbers are correct even if specification starts with empty lines.
+///
+/// Messages are not the same as messages of API: API reports most syntax errors as
+/// `Syntax error.`, while lint describes what is expected (e.g. `` expected `:` after receiver of the signal ``).
+/// Messages about activations are the same as messages of API.
+pub fn lint(spec: &str) -> Vec<DiagramError> {
+    let (diagram, mut errors) = syntax::parse_with_errors(spec);
+    errors.extend(diagram.check());
+    errors.sort_by_key(|error| error.span.start);
+    errors.into_iter().map(diagram_error).collect()
+}
+
+fn diagram_error(error: SyntaxError) -> DiagramError {
+    DiagramError {
+        raw_description: format!("Line {}: {}", error.span.line, error.message),
+        description: error.message,
+        line_number: error.span.line as i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lint::lint;
+    use crate::types::DiagramError;
+
+    use std::fs;
+
+    #[test]
+    fn lint_test() {
+        let spec = fs::read_to_string("sample_diagrams/02_errors_in_diag.wsd").unwrap();
+        let errors = lint(&spec);
+        assert_eq!(
+            errors.iter().map(|error| (error.line_number, error.description.as_str())).collect::<Vec<_>>(),
+            vec![
+                (1, "expected `:` after receiver of the signal"),
+                (3, "Deactivate: A was not activated."),
+            ]
+        );
+        assert_eq!(
+            DiagramError::from_wsd_error_str(&errors[1].raw_description).unwrap(),
+            errors[1]
+        );
+
+        assert_eq!(lint("\n\nalt x\n    A->B: hi\n")[0].line_number, 3);
+        assert!(lint(&fs::read_to_string("sample_diagrams/01_simple_diag.wsd").unwrap()).is_empty());
+        assert!(lint(&fs::read_to_string("sample_diagrams/03_premium_features.wsd").unwrap()).is_empty());
+        // Statements which are not modeled by parser are not errors
+        assert!(lint("autonumber 1\nA->B: hi\ngroup g\n    ref over A,B: r\nend\n==x==\nspace\ndestroy B\n").is_empty());
+    }
+}
@@ -1,35 +1,340 @@
 extern crate wsdclient;
+extern crate log;
 
-use wsdclient::config::Config;
+use wsdclient::config::{Command, Config, ConfigFile};
 use crate::wsdclient::types::WSDEnum;
-use wsdclient::client::get_diagram;
-use std::fs::File;
-use std::io::{Read, Write, stdin};
+use wsdclient::client::{WsdClient, WSDStreamResult};
+use wsdclient::error::WsdError;
+use wsdclient::lint::lint;
+use wsdclient::syntax;
+use wsdclient::rate_limit::RateLimiter;
+use wsdclient::types::{DiagramError, Format};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write, stdin};
 use std::error::Error;
 
-fn main() -> Result<(), Box<Error>> {
-    // TODO(mkl): add option to print requests and responses
+use log::{Level, Log, Metadata, Record};
+
+// Prints log records of this crate to STDERR. Records of dependencies (http client) are skipped
+struct StderrLogger {
+    level: Level,
+}
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level && metadata.target().starts_with("wsdclient")
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+// Installs logger for given number of -v flags
+fn init_logger(verbosity: u64) {
+    let level = match verbosity {
+        0 => Level::Warn,
+        1 => Level::Debug,
+        _ => Level::Trace,
+    };
+    let logger = Box::new(StderrLogger { level });
+    if log::set_boxed_logger(logger).is_ok() {
+        log::set_max_level(level.to_level_filter());
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
     let config = Config::from_command_line()
-        .map_err(|err| format!("error parsing command line options: {:?}", err))?;
-
-    let mut diagram: Vec<u8> = vec![];
-    if let Some(ref input_file) = config.input_file {
-        File::open(input_file)
-            .map_err(|err| format!("error opening input file {} : {:?}", input_file, err))?
-            .read_to_end(&mut diagram)
-            .map_err(|err| format!("error reading input file {} : {:?}", input_file, err))?;
-    } else {
+        .map_err(|err| format!("error parsing command line options: {}", err))?;
+    init_logger(config.verbosity);
+    match config.command {
+        Command::AuthStatus => return auth_status(&config),
+        Command::CheckKey => return check_key(&config),
+        Command::Lint => return lint_inputs(&config),
+        Command::Fmt { check } => return format_inputs(&config, check),
+        Command::Render => {}
+    }
+    check_parameters(&config)?;
+
+    let (inputs, mut failures) = read_inputs(&config)?;
+    let client = build_client(&config)?;
+
+    let total = config.input_files.len().max(1);
+    let mut jobs = vec![];
+    for (index, (input_file, diagram_str)) in inputs.iter().enumerate() {
+        let output_file = config.output_file_for(input_file.as_ref().map(String::as_str));
+        if Some(&output_file) == input_file.as_ref() {
+            let err = format!("output file is the same as input file: {}. Use --output option", output_file);
+            report_failure(total, input_file, err.into())?;
+            failures += 1;
+        } else {
+            jobs.push((index, diagram_str.clone(), config.plot_parameters.clone(), PartFile::new(&output_file)));
+        }
+    }
+    // Diagrams are streamed into output files without holding them in memory
+    let results = client.render_batch_to_writers(jobs, &config.batch_options);
+
+    for batch_result in results {
+        let (ref input_file, ref diagram_str) = inputs[batch_result.id];
+        if let Err(err) = save_result(&config, input_file.as_ref().map(String::as_str), diagram_str, batch_result.result) {
+            report_failure(total, input_file, err)?;
+            failures += 1;
+        }
+    }
+    if failures > 0 {
+        return Err(format!("{} of {} diagrams failed", failures, total).into());
+    }
+    Ok(())
+}
+
+// Single input fails with its own error. Failures of many inputs are printed, so others are still rendered
+fn report_failure(total: usize, input_file: &Option<String>, err: Box<dyn Error>) -> Result<(), Box<dyn Error>> {
+    if total == 1 {
+        return Err(err);
+    }
+    eprintln!("{}: {}", input_name(input_file), err);
+    Ok(())
+}
+
+// Diagram is written next to output file and renamed when it is complete and has no fatal errors,
+// so failed diagram does not replace previous output. File is created only when diagram
+// starts arriving, so pending jobs of the batch do not hold open files
+struct PartFile {
+    path: String,
+    file: Option<BufWriter<File>>,
+}
+
+impl PartFile {
+    fn new(output_file: &str) -> PartFile {
+        PartFile {
+            path: part_file_path(output_file),
+            file: None,
+        }
+    }
+}
+
+impl Write for PartFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() {
+            self.file = Some(BufWriter::new(File::create(&self.path)?));
+        }
+        self.file.as_mut().expect("part file is open").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file {
+            Some(ref mut file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn part_file_path(output_file: &str) -> String {
+    format!("{}.part", output_file)
+}
+
+// Input name (None for STDIN) and its content
+type Input = (Option<String>, String);
+
+// Reads input files or STDIN. Returns inputs and number of files which cannot be read.
+// Error is returned only for single input file
+fn read_inputs(config: &Config) -> Result<(Vec<Input>, usize), Box<dyn Error>> {
+    let mut inputs: Vec<Input> = vec![];
+    let mut failures = 0;
+    if config.input_files.is_empty() {
+        let mut diagram: Vec<u8> = vec![];
         stdin()
             .read_to_end(& mut diagram)
             .map_err(|err| format!("error reading from STDIN: {:?}", err))?;
+        inputs.push((None, String::from_utf8_lossy(&diagram[..]).into_owned()));
+    } else {
+        for input_file in &config.input_files {
+            let mut diagram: Vec<u8> = vec![];
+            let read_result = File::open(input_file)
+                .map_err(|err| format!("error opening input file {} : {:?}", input_file, err))
+                .and_then(|mut f| f.read_to_end(&mut diagram)
+                    .map_err(|err| format!("error reading input file {} : {:?}", input_file, err)));
+            match read_result {
+                Ok(_) => inputs.push((Some(input_file.clone()), String::from_utf8_lossy(&diagram[..]).into_owned())),
+                Err(err) => {
+                    if config.input_files.len() == 1 {
+                        return Err(err.into());
+                    }
+                    eprintln!("{}", err);
+                    failures += 1;
+                }
+            }
+        }
     }
+    Ok((inputs, failures))
+}
 
-    let diagram_str = String::from_utf8_lossy(&diagram[..]);
-    let result = get_diagram(&diagram_str, &config.plot_parameters)
-        .map_err(|err| format!("error getting diagram: {:?}", err))?;
+// Checks inputs without contacting the server. It is an error if any input has errors
+fn lint_inputs(config: &Config) -> Result<(), Box<dyn Error>> {
+    let (inputs, failures) = read_inputs(config)?;
+    let mut errors_count = 0;
+    for (input_file, diagram_str) in &inputs {
+        let lines = diagram_str.split('\n').collect::<Vec<&str>>();
+        for error in lint(diagram_str) {
+            eprintln!("{}:{} : {}", input_name(input_file), error.line_number, error.description);
+            if let Some(line) = lines.get((error.line_number - 1) as usize) {
+                eprintln!("{}\n", line);
+            }
+            errors_count += 1;
+        }
+    }
+    if failures > 0 {
+        return Err(format!("{} of {} files cannot be read", failures, config.input_files.len()).into());
+    }
+    if errors_count > 0 {
+        return Err(format!("Number of errors in diagrams: {}", errors_count).into());
+    }
+    Ok(())
+}
 
-    if result.actual_format != config.plot_parameters.format {
-        let error_msg = format!("WARNING: Actual format `{}` is different from requested format `{}`\nMaybe you do not provide correct api_key for premium features (like pdf or svg formats)", result.actual_format.wsd_value(), config.plot_parameters.format.wsd_value());
+// Rewrites inputs into canonical layout. STDIN is written into STDOUT.
+// With `check` nothing is written, it is an error if some inputs are not formatted
+fn format_inputs(config: &Config, check: bool) -> Result<(), Box<dyn Error>> {
+    let (inputs, mut failures) = read_inputs(config)?;
+    let mut unformatted = 0;
+    for (input_file, diagram_str) in &inputs {
+        let formatted = match syntax::format(diagram_str) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                eprintln!("{}:{} : {}", input_name(input_file), err.span.line, err.message);
+                failures += 1;
+                continue;
+            }
+        };
+        if check {
+            if formatted != *diagram_str {
+                eprintln!("{} is not formatted", input_name(input_file));
+                unformatted += 1;
+            }
+        } else if let Some(input_file) = input_file {
+            if formatted != *diagram_str {
+                File::create(input_file)
+                    .and_then(|mut f| f.write_all(formatted.as_bytes()))
+                    .map_err(|err| format!("cannot write file: {} : {:?}", input_file, err))?;
+            }
+        } else {
+            print!("{}", formatted);
+        }
+    }
+    if failures > 0 {
+        return Err(format!("{} of {} files cannot be formatted", failures, config.input_files.len().max(1)).into());
+    }
+    if unformatted > 0 {
+        return Err(format!("Number of files which are not formatted: {}", unformatted).into());
+    }
+    Ok(())
+}
+
+fn build_client(config: &Config) -> Result<WsdClient, Box<dyn Error>> {
+    if config.rate_limit.is_some() {
+        RateLimiter::global().set_limit(config.rate_limit);
+    }
+    let mut client_builder = WsdClient::builder()
+        .connection_options(config.connection_options.clone());
+    if let Some(ref cache_options) = config.cache {
+        client_builder = client_builder.cache(cache_options.clone());
+    }
+    if let Some(ref fixtures) = config.fixtures {
+        client_builder = client_builder.fixtures(fixtures.clone());
+    }
+    let client = client_builder
+        .build()
+        .map_err(|err| format!("error creating client: {}", err))?;
+    Ok(client)
+}
+
+// Reports where API key is taken from. It is an error if there is no API key
+fn auth_status(config: &Config) -> Result<(), Box<dyn Error>> {
+    match config.api_key_source {
+        Some(ref source) => {
+            println!("API key is set from {}", source);
+            Ok(())
+        }
+        None => Err(api_key_not_set()),
+    }
+}
+
+// Reports formats unlocked by API key and quota. It is an error if key does not unlock premium formats
+fn check_key(config: &Config) -> Result<(), Box<dyn Error>> {
+    let (api_key, source) = match (&config.plot_parameters.api_key, &config.api_key_source) {
+        (Some(api_key), Some(source)) => (api_key, source),
+        _ => return Err(api_key_not_set()),
+    };
+    let checked = build_client(config)?
+        .check_key(api_key)
+        .map_err(|err| format!("error checking API key: {}", err))?;
+    let formats = checked.available_formats
+        .iter()
+        .map(WSDEnum::wsd_value)
+        .collect::<Vec<String>>()
+        .join(", ");
+    println!("Available formats: {}", formats);
+    for (name, value) in &checked.quota {
+        println!("{}: {}", name, value);
+    }
+    if checked.premium {
+        println!("API key from {} unlocks premium formats", source);
+        Ok(())
+    } else {
+        Err(format!("API key from {} does not unlock premium formats", source).into())
+    }
+}
+
+fn api_key_not_set() -> Box<dyn Error> {
+    let config_file = ConfigFile::user_path()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| "config file".to_owned());
+    format!(
+        "API key is not set. Use --api-key-file, --api-key-command, WEBSEQUENCEDIAGRAM_API_KEY environment variable or api_key_command/api_key in {}",
+        config_file
+    ).into()
+}
+
+// Reports problems in plot parameters before any request is sent. Returns error if they are fatal
+fn check_parameters(config: &Config) -> Result<(), Box<dyn Error>> {
+    let warnings = config.plot_parameters.validate();
+    for warning in &warnings {
+        eprintln!("WARNING: {}", warning);
+    }
+    if config.is_warnings_fatal && !warnings.is_empty() {
+        return Err(format!("Number of warnings in plot parameters: {}. Exiting.", warnings.len()).into());
+    }
+    Ok(())
+}
+
+fn input_name(input_file: &Option<String>) -> String {
+    input_file.clone().unwrap_or_else(||"<STDIN>".to_owned())
+}
+
+// Checks result and moves diagram into output file derived from input file.
+// Diagram which failed or has fatal errors is removed
+fn save_result(config: &Config, input_file: Option<&str>, diagram_str: &str, result: Result<WSDStreamResult, WsdError>) -> Result<(), Box<dyn Error>> {
+    let output_file = config.output_file_for(input_file);
+    let part_file = part_file_path(&output_file);
+    let saved = result
+        .map_err(|err| -> Box<dyn Error> { format!("error getting diagram: {}", err).into() })
+        .and_then(|result| check_result(config, input_file, diagram_str, &result.errors, &result.actual_format))
+        .and_then(|_| fs::rename(&part_file, &output_file)
+            .map_err(|err| -> Box<dyn Error> { format!("cannot write output file: {} : {:?}", &output_file, err).into() }));
+    if saved.is_err() {
+        let _ = fs::remove_file(&part_file);
+    }
+    saved
+}
+
+// Reports errors in diagram and format mismatch. Returns error if they are fatal
+fn check_result(config: &Config, input_file: Option<&str>, diagram_str: &str, errors: &[DiagramError], actual_format: &Format) -> Result<(), Box<dyn Error>> {
+    if *actual_format != config.plot_parameters.format {
+        let error_msg = format!("WARNING: Actual format `{}` is different from requested format `{}`\nMaybe you do not provide correct api_key for premium features (like pdf or svg formats)", actual_format.wsd_value(), config.plot_parameters.format.wsd_value());
         if config.is_errors_fatal {
             return Err(error_msg.into())
         } else {
@@ -37,7 +342,7 @@ fn main() -> Result<(), Box<Error>> {
         }
     }
 
-    if !result.errors.is_empty() {
+    if !errors.is_empty() {
         let lines = diagram_str.split('\n').collect::<Vec<&str>>();
         // There is a bug in websequencediagrams
         // if file starts with empty strings
@@ -47,8 +352,8 @@ fn main() -> Result<(), Box<Error>> {
         } else {
             0
         };
-        for error in &result.errors {
-            let inp_file_name = config.input_file.clone().unwrap_or_else(||"<STDIN>".to_owned());
+        for error in errors {
+            let inp_file_name = input_file.unwrap_or("<STDIN>");
             let line_number = error.line_number + delta;
             if line_number  > (lines.len() as i32) || line_number  < 1 {
                 return Err(format!("incorect resulting error line number: {}. Number of lines in input: {}", line_number, lines.len()).into())
@@ -57,14 +362,9 @@ fn main() -> Result<(), Box<Error>> {
             eprintln!("{}\n", lines[(line_number-1) as usize])
         }
         if config.is_errors_fatal {
-            return Err(format!("Number of errors in diagram: {}. Exiting.", result.errors.len()).into())
+            return Err(format!("Number of errors in diagram: {}. Exiting.", errors.len()).into())
         }
     }
-
-    let mut f = File::create(&config.output_file)
-        .map_err(|err| format!("cannot open output file: {} : {:?}", &config.output_file, err))?;
-    f.write_all(&result.diagram[..])
-        .map_err(|err| format!("cannot write to output file : {} : {:?}", &config.output_file, err))?;
     Ok(())
 }
 
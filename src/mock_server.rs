@@ -0,0 +1,352 @@
+use crate::types::{Format, WSDEnum};
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+/// Represent behaviour of the mock server. It can be changed while server is running
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockBehavior {
+    /// Errors returned for every diagram. E.g. "Line 1: Syntax error."
+    pub errors: Vec<String>,
+
+    /// Token used in url of the next diagram instead of generated one (`mscMOCK1`, `mscMOCK2`, ...)
+    pub image_token: Option<String>,
+
+    /// API key required for premium formats (pdf, svg). Without correct key png diagram is returned,
+    /// like the real API does. None means any non-empty key is accepted
+    pub api_key: Option<String>,
+
+    /// Delay before every response
+    pub delay: Duration,
+}
+
+impl Default for MockBehavior {
+    fn default() -> MockBehavior {
+        MockBehavior {
+            errors: vec![],
+            image_token: None,
+            api_key: None,
+            delay: Duration::from_secs(0),
+        }
+    }
+}
+
+/// Represent request received by the mock server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockRequest {
+    /// HTTP method. E.g. "POST"
+    pub method: String,
+
+    /// Path with query. E.g. "/index.php?png=mscMOCK1"
+    pub path: String,
+
+    /// Fields of the form sent in the body. Empty for GET requests
+    pub form: Vec<(String, String)>,
+}
+
+impl MockRequest {
+    /// Value of the form field
+    pub fn form_value(&self, name: &str) -> Option<&str> {
+        self.form
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Response of the mock server
+#[derive(Debug, Clone)]
+struct Reply {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    behavior: MockBehavior,
+    message_failures: VecDeque<Reply>,
+    image_failures: VecDeque<Reply>,
+    // Image path (e.g. "?png=mscMOCK1") -> content
+    images: HashMap<String, Vec<u8>>,
+    requests: Vec<MockRequest>,
+    next_token: u32,
+}
+
+/// In-process mock of websequencediagrams API. Server is stopped when it is dropped.
+///
+/// Server answers POST to `index.php` with JSON like `{"img": "?png=mscMOCK1", "errors": []}`
+/// and GET of `index.php?png=mscMOCK1` with the image. Image content is `MockServer::image(format, spec)`.
+///
+/// Example:
+/// ```
+/// use wsdclient::mock_server::MockServer;
+/// use wsdclient::{WsdClient, Format};
+///
+/// let server = MockServer::start();
+/// let client = WsdClient::builder().base_url(server.base_url()).build().unwrap();
+/// let rez = client.get_diagram_with_defaults("A->B: text").unwrap();
+/// assert_eq!(rez.diagram, MockServer::image(&Format::Png, "A->B: text"));
+/// ```
+#[derive(Debug)]
+pub struct MockServer {
+    base_url: String,
+    state: Arc<Mutex<MockState>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl MockServer {
+    /// Start server with default behaviour on a random local port
+    pub fn start() -> MockServer {
+        MockServer::with_behavior(MockBehavior::default())
+    }
+
+    /// Start server with given behaviour on a random local port
+    pub fn with_behavior(behavior: MockBehavior) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("mock server bind");
+        let base_url = format!("http://{}", listener.local_addr().expect("mock server address"));
+        let state = Arc::new(Mutex::new(MockState {
+            behavior,
+            ..MockState::default()
+        }));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let server_state = state.clone();
+        let server_stopped = stopped.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if server_stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let state = server_state.clone();
+                    thread::spawn(move || handle_connection(stream, &state));
+                }
+            }
+        });
+
+        MockServer {
+            base_url,
+            state,
+            stopped,
+        }
+    }
+
+    /// Address of the server to be used as base url of the client. E.g. "http://127.0.0.1:34567"
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Content of the image returned for the diagram
+    pub fn image(format: &Format, spec: &str) -> Vec<u8> {
+        format!("{} image of: {}", format.wsd_value(), spec).into_bytes()
+    }
+
+    /// Current behaviour
+    pub fn behavior(&self) -> MockBehavior {
+        self.lock().behavior.clone()
+    }
+
+    /// Change behaviour for next requests
+    pub fn set_behavior(&self, behavior: MockBehavior) {
+        self.lock().behavior = behavior;
+    }
+
+    /// Respond to the next request for the diagram (POST to `index.php`) with given status and body
+    pub fn fail_next_message(&self, status: u16, body: &str) {
+        self.fail_next_message_with_headers(status, &[], body);
+    }
+
+    /// Same as `fail_next_message` but with additional headers. E.g. `Retry-After`
+    pub fn fail_next_message_with_headers(&self, status: u16, headers: &[(&str, &str)], body: &str) {
+        self.lock().message_failures.push_back(reply(status, headers, body));
+    }
+
+    /// Respond to the next request for the image with given status and body
+    pub fn fail_next_image(&self, status: u16, body: &str) {
+        self.lock().image_failures.push_back(reply(status, &[], body));
+    }
+
+    /// All requests received by the server so far
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.lock().requests.clone()
+    }
+
+    /// Number of requests for the diagram (POST to `index.php`) received so far
+    pub fn message_requests_count(&self) -> usize {
+        self.lock().requests.iter().filter(|request| request.method == "POST").count()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        lock(&self.state)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wake up accepting thread, so it can exit
+        let _ = TcpStream::connect(self.base_url.trim_start_matches("http://"));
+    }
+}
+
+fn reply(status: u16, headers: &[(&str, &str)], body: &str) -> Reply {
+    Reply {
+        status,
+        headers: headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        body: body.as_bytes().to_vec(),
+    }
+}
+
+fn lock(state: &Mutex<MockState>) -> MutexGuard<'_, MockState> {
+    // Panic in one test should not break other requests
+    state.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn handle_connection(mut stream: TcpStream, state: &Mutex<MockState>) {
+    let request = match read_request(&mut stream) {
+        Some(request) => request,
+        None => return,
+    };
+    let (delay, response) = {
+        let mut state = lock(state);
+        state.requests.push(request.clone());
+        let response = respond(&mut state, &request);
+        (state.behavior.delay, response)
+    };
+    if delay > Duration::from_secs(0) {
+        thread::sleep(delay);
+    }
+    let _ = write_response(&mut stream, &response);
+}
+
+fn respond(state: &mut MockState, request: &MockRequest) -> Reply {
+    let path = request.path.trim_start_matches('/');
+    if !path.starts_with("index.php") {
+        return reply(404, &[], "Not Found");
+    }
+    if request.method == "POST" {
+        if let Some(failure) = state.message_failures.pop_front() {
+            return failure;
+        }
+        return render(state, request);
+    }
+    if let Some(failure) = state.image_failures.pop_front() {
+        return failure;
+    }
+    let image_path = &path["index.php".len()..];
+    match state.images.get(image_path) {
+        Some(image) => Reply {
+            status: 200,
+            headers: vec![],
+            body: image.clone(),
+        },
+        None => reply(404, &[], "Not Found"),
+    }
+}
+
+// Handles request for the diagram. Premium formats are downgraded to png without correct api key
+fn render(state: &mut MockState, request: &MockRequest) -> Reply {
+    let spec = request.form_value("message").unwrap_or("").to_owned();
+    let requested_format = request.form_value("format")
+        .and_then(Format::from_str)
+        .unwrap_or(Format::Png);
+    let api_key_is_valid = match (request.form_value("apikey"), state.behavior.api_key.as_ref()) {
+        (Some(api_key), Some(expected)) => api_key == expected,
+        (Some(api_key), None) => !api_key.is_empty(),
+        (None, _) => false,
+    };
+    let format = if requested_format.premium_feature() && !api_key_is_valid {
+        Format::Png
+    } else {
+        requested_format
+    };
+
+    state.next_token += 1;
+    let token = state.behavior.image_token
+        .clone()
+        .unwrap_or_else(|| format!("mscMOCK{}", state.next_token));
+    let image_path = format!("?{}={}", format.wsd_value(), token);
+    state.images.insert(image_path.clone(), MockServer::image(&format, &spec));
+
+    let body = serde_json::json!({
+        "img": image_path,
+        "errors": state.behavior.errors,
+        "page": 0,
+        "numPages": 1,
+    });
+    Reply {
+        status: 200,
+        headers: vec![("Content-Type".to_owned(), "application/json".to_owned())],
+        body: body.to_string().into_bytes(),
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<MockRequest> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_owned();
+    let path = parts.next()?.to_owned();
+
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let mut header = line.splitn(2, ':');
+        if let (Some(name), Some(value)) = (header.next(), header.next()) {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().ok()?;
+            }
+        }
+    }
+
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).ok()?;
+    Some(MockRequest {
+        method,
+        path,
+        form: parse_form(&String::from_utf8_lossy(&body)),
+    })
+}
+
+// Parses application/x-www-form-urlencoded body
+fn parse_form(body: &str) -> Vec<(String, String)> {
+    if body.is_empty() {
+        return vec![];
+    }
+    match reqwest::Url::parse(&format!("http://localhost/?{}", body)) {
+        Ok(url) => url.query_pairs().into_owned().collect(),
+        Err(_) => vec![],
+    }
+}
+
+fn write_response(stream: &mut TcpStream, response: &Reply) -> std::io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
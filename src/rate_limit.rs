@@ -0,0 +1,207 @@
+use futures::Future;
+use serde::{Serialize, Deserialize};
+use tokio_timer::Delay;
+
+use crate::error::WsdError;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Pause of requests after API responded with 429 Too Many Requests without `Retry-After` header
+pub const DEFAULT_RATE_LIMIT_PAUSE: Duration = Duration::from_secs(1);
+
+/// Represent limit of the number of requests to API
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Average number of requests per second
+    pub requests_per_second: f64,
+
+    /// Maximum number of requests which can be sent at once after a period of inactivity.
+    /// 0 is treated as 1
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Limit without bursts: requests are spread evenly in time
+    pub fn per_second(requests_per_second: f64) -> RateLimit {
+        RateLimit {
+            requests_per_second,
+            burst: 1,
+        }
+    }
+}
+
+/// Token bucket limiting the rate of requests to API.
+///
+/// Each request to API (both for diagram and for image) takes one token.
+/// Tokens are added with the rate of `RateLimit::requests_per_second` up to `RateLimit::burst`.
+/// When API responds with 429 Too Many Requests all requests are paused for the time
+/// from `Retry-After` header (or `DEFAULT_RATE_LIMIT_PAUSE`), even if there is no limit.
+///
+/// By default all clients share `RateLimiter::global()`, so the limit applies to the whole process.
+#[derive(Debug)]
+pub struct RateLimiter {
+    state: Mutex<LimiterState>,
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    limit: Option<RateLimit>,
+    // Can be negative if tokens are reserved by waiting requests
+    tokens: f64,
+    refilled_at: Instant,
+    paused_until: Option<Instant>,
+}
+
+lazy_static! {
+    static ref GLOBAL_RATE_LIMITER: Arc<RateLimiter> = Arc::new(RateLimiter::new(None));
+}
+
+impl RateLimiter {
+    /// Create limiter. None means there is no limit, but limiter still reacts to 429 responses
+    pub fn new(limit: Option<RateLimit>) -> RateLimiter {
+        RateLimiter {
+            state: Mutex::new(LimiterState {
+                limit,
+                tokens: limit.map(|limit| f64::from(limit.burst.max(1))).unwrap_or(0.0),
+                refilled_at: Instant::now(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// Limiter shared by all clients in the process unless other limiter is set
+    /// with `WsdClientBuilder::rate_limiter`. By default it has no limit
+    pub fn global() -> Arc<RateLimiter> {
+        GLOBAL_RATE_LIMITER.clone()
+    }
+
+    /// Current limit
+    pub fn limit(&self) -> Option<RateLimit> {
+        self.lock().limit
+    }
+
+    /// Change the limit. Requests which are already waiting are not affected
+    pub fn set_limit(&self, limit: Option<RateLimit>) {
+        let mut state = self.lock();
+        state.limit = limit;
+        state.tokens = limit.map(|limit| f64::from(limit.burst.max(1))).unwrap_or(0.0);
+        state.refilled_at = Instant::now();
+    }
+
+    /// Pause all requests for given time
+    pub fn pause(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut state = self.lock();
+        match state.paused_until {
+            Some(paused_until) if paused_until >= until => {}
+            _ => state.paused_until = Some(until),
+        }
+    }
+
+    /// Take token blocking current thread until it is available
+    pub fn acquire(&self) {
+        let wait = self.reserve();
+        if wait > Duration::from_secs(0) {
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Take token without blocking. Returned future should be run on tokio runtime
+    pub fn acquire_async(&self) -> impl Future<Item=(), Error=WsdError> {
+        // If timer is not available request is sent immediately
+        Delay::new(Instant::now() + self.reserve()).then(|_| Ok(()))
+    }
+
+    // Reserves token and returns time to wait before it can be used
+    fn reserve(&self) -> Duration {
+        let now = Instant::now();
+        let mut state = self.lock();
+        let pause = match state.paused_until {
+            Some(paused_until) if paused_until > now => paused_until - now,
+            _ => Duration::from_secs(0),
+        };
+        let limit = match state.limit {
+            Some(limit) if limit.requests_per_second > 0.0 && limit.requests_per_second.is_finite() => limit,
+            _ => return pause,
+        };
+        let elapsed = now - state.refilled_at;
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+        state.tokens = (state.tokens + elapsed * limit.requests_per_second).min(f64::from(limit.burst.max(1)));
+        state.refilled_at = now;
+        state.tokens -= 1.0;
+        let token_wait = if state.tokens < 0.0 {
+            Duration::from_nanos((-state.tokens / limit.requests_per_second * 1e9) as u64)
+        } else {
+            Duration::from_secs(0)
+        };
+        token_wait.max(pause)
+    }
+
+    // Pauses requests if API responded with 429 Too Many Requests
+    pub(crate) fn observe(&self, error: &WsdError) {
+        if let WsdError::RateLimited { retry_after, .. } = error {
+            self.pause(retry_after.unwrap_or(DEFAULT_RATE_LIMIT_PAUSE));
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, LimiterState> {
+        // State is always consistent, so it is used even if other thread panicked
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Parses value of `Retry-After` header. Only delay in seconds is supported, not HTTP date
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rate_limit::{parse_retry_after, RateLimit, RateLimiter};
+    use crate::error::WsdError;
+
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn token_bucket_test() {
+        let limiter = RateLimiter::new(Some(RateLimit { requests_per_second: 20.0, burst: 2 }));
+        let start = Instant::now();
+        for _ in 0..6 {
+            limiter.acquire();
+        }
+        // Burst of 2 requests is not delayed, other 4 are spaced by 50ms
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        let unlimited = RateLimiter::new(None);
+        let start = Instant::now();
+        for _ in 0..100 {
+            unlimited.acquire();
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn pause_on_too_many_requests_test() {
+        let limiter = RateLimiter::new(None);
+        limiter.observe(&WsdError::Status { url: "".to_owned(), status: 500, body: "".to_owned() });
+        let start = Instant::now();
+        limiter.acquire();
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        limiter.observe(&WsdError::RateLimited {
+            url: "".to_owned(),
+            retry_after: Some(Duration::from_millis(200)),
+            body: "".to_owned(),
+        });
+        let start = Instant::now();
+        limiter.acquire();
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[test]
+    fn parse_retry_after_test() {
+        assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    }
+}
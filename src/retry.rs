@@ -0,0 +1,213 @@
+use futures::future::{self, Loop};
+use futures::Future;
+use rand::Rng;
+use tokio_timer::Delay;
+
+use crate::error::WsdError;
+
+use std::error::Error;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Represent policy of retrying failed requests to API.
+///
+/// Policy is applied independently to each request: to the request for the diagram (POST to `index.php`)
+/// and to the request for the image.
+/// Delay before retry `n` (starting from 1) is `backoff_base * 2^(n-1)` but not more than `max_backoff`.
+/// Then it is randomly changed by up to `jitter` fraction of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts for each request including the first one. 1 means no retries
+    pub max_attempts: u32,
+
+    /// Delay before the first retry
+    pub backoff_base: Duration,
+
+    /// Maximum delay between retries (before jitter is applied)
+    pub max_backoff: Duration,
+
+    /// Fraction of the delay which is randomised. Should be between 0.0 and 1.0.
+    /// E.g. 0.2 means actual delay is between 80% and 120% of computed one
+    pub jitter: f64,
+
+    /// HTTP status codes of response which are retried.
+    /// Retry after 429 Too Many Requests also waits until rate limiter is resumed
+    pub retryable_statuses: Vec<u16>,
+
+    /// Kinds of IO errors (during connecting, sending request or reading response) which are retried
+    pub retryable_io_errors: Vec<io::ErrorKind>,
+
+    /// Retry network errors not caused by IO error. E.g. server closed connection before sending response
+    pub retry_connection_errors: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff_base: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            jitter: 0.2,
+            retryable_statuses: vec![429, 500, 502, 503, 504],
+            retryable_io_errors: vec![
+                io::ErrorKind::ConnectionRefused,
+                io::ErrorKind::ConnectionReset,
+                io::ErrorKind::ConnectionAborted,
+                io::ErrorKind::BrokenPipe,
+                io::ErrorKind::TimedOut,
+                io::ErrorKind::UnexpectedEof,
+                io::ErrorKind::Interrupted,
+            ],
+            retry_connection_errors: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy without retries. Each request is performed once
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Can request which failed with this error be retried
+    pub fn is_retryable(&self, error: &WsdError) -> bool {
+        match error {
+            WsdError::Status { status, .. } => self.retryable_statuses.contains(status),
+            WsdError::RateLimited { .. } => self.retryable_statuses.contains(&429),
+            WsdError::Io { source, .. } => self.retryable_io_errors.contains(&source.kind()),
+            WsdError::Network { source, .. } => match io_error_kind(source) {
+                Some(kind) => self.retryable_io_errors.contains(&kind),
+                None => self.retry_connection_errors && (source.is_http() || source.is_timeout()),
+            },
+            _ => false,
+        }
+    }
+
+    /// Delay before retry number `retry` (starting from 1) without jitter
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        match self.backoff_base.checked_mul(factor) {
+            Some(delay) if delay < self.max_backoff => delay,
+            _ => self.max_backoff,
+        }
+    }
+
+    // Delay before retry number `retry` with jitter applied
+    fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.backoff(retry);
+        let jitter = self.jitter.max(0.0).min(1.0);
+        if jitter == 0.0 {
+            return backoff;
+        }
+        let factor = rand::thread_rng().gen_range(1.0 - jitter, 1.0 + jitter);
+        let nanos = (backoff.as_secs() as f64 * 1e9 + f64::from(backoff.subsec_nanos())) * factor;
+        Duration::from_nanos(nanos as u64)
+    }
+}
+
+// Finds kind of IO error which caused network error (e.g. connection refused)
+fn io_error_kind(error: &reqwest::Error) -> Option<io::ErrorKind> {
+    let mut cause: Option<&(dyn Error + 'static)> = error.get_ref().map(|err| err as &(dyn Error + 'static));
+    while let Some(err) = cause {
+        if let Some(io_error) = err.downcast_ref::<io::Error>() {
+            return Some(io_error.kind());
+        }
+        cause = err.source();
+    }
+    None
+}
+
+/// Performs operation retrying it according to policy.
+/// Returns result of the last attempt and number of attempts made
+pub(crate) fn retry<T, F>(policy: &RetryPolicy, mut operation: F) -> Result<(T, u32), WsdError>
+    where
+        F: FnMut() -> Result<T, WsdError>,
+{
+    let mut attempt = 1;
+    loop {
+        match operation() {
+            Ok(x) => return Ok((x, attempt)),
+            Err(err) => {
+                if attempt >= policy.max_attempts || !policy.is_retryable(&err) {
+                    return Err(err);
+                }
+                std::thread::sleep(policy.delay(attempt));
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Async counterpart of `retry`. Delays are performed using tokio timer
+pub(crate) fn retry_async<T, F, R>(policy: RetryPolicy, operation: F) -> impl Future<Item=(T, u32), Error=WsdError>
+    where
+        F: FnMut() -> R,
+        R: Future<Item=T, Error=WsdError>,
+{
+    future::loop_fn((operation, 1), move |(mut operation, attempt)| {
+        let policy = policy.clone();
+        operation().then(move |rez| {
+            let retry_delay = match rez {
+                Ok(x) => return future::Either::A(future::ok(Loop::Break((x, attempt)))),
+                Err(ref err) if attempt < policy.max_attempts && policy.is_retryable(err) => policy.delay(attempt),
+                Err(err) => return future::Either::A(future::err(err)),
+            };
+            // If timer is not available retry is performed immediately
+            future::Either::B(
+                Delay::new(Instant::now() + retry_delay)
+                    .then(move |_| Ok(Loop::Continue((operation, attempt + 1))))
+            )
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::retry::RetryPolicy;
+    use crate::error::WsdError;
+
+    use std::time::Duration;
+
+    #[test]
+    fn backoff_test() {
+        let policy = RetryPolicy {
+            backoff_base: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.backoff(5), Duration::from_millis(1000));
+        assert_eq!(policy.backoff(100), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn delay_jitter_test() {
+        let policy = RetryPolicy {
+            backoff_base: Duration::from_millis(100),
+            jitter: 0.5,
+            ..RetryPolicy::default()
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(1);
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(150));
+        }
+    }
+
+    #[test]
+    fn is_retryable_test() {
+        let policy = RetryPolicy::default();
+        let status_error = |status| WsdError::Status { url: "".to_owned(), status, body: "".to_owned() };
+        assert!(policy.is_retryable(&status_error(503)));
+        assert!(!policy.is_retryable(&status_error(404)));
+        assert!(policy.is_retryable(&WsdError::Io {
+            url: "".to_owned(),
+            source: std::io::ErrorKind::ConnectionReset.into(),
+        }));
+        assert!(!policy.is_retryable(&WsdError::InvalidImageUrl { url: "".to_owned() }));
+    }
+}
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Deserializer, Serializer};
+
+use std::fmt;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+/// Text shown instead of the secret value
+pub const REDACTED: &str = "<redacted>";
+
+/// Represent secret value like API key.
+///
+/// Value is never shown by `Debug` or `Display`, use `expose` to get it.
+/// Memory with the value is overwritten with zeros when secret is dropped.
+///
+/// Secret is deserialized from plain string but it does not implement `Serialize`,
+/// so it cannot be written by accident. Serialization is opt-in:
+/// ```
+/// use serde::Serialize;
+/// use wsdclient::secret::Secret;
+///
+/// #[derive(Serialize)]
+/// struct Credentials {
+///     #[serde(serialize_with = "wsdclient::secret::serialize_exposed")]
+///     api_key: Secret,
+/// }
+///
+/// let credentials = Credentials { api_key: Secret::new("key") };
+/// assert_eq!(format!("{:?}", credentials.api_key), "Secret(<redacted>)");
+/// assert_eq!(serde_json::to_string(&credentials).unwrap(), r#"{"api_key":"key"}"#);
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new<S: Into<String>>(value: S) -> Secret {
+        Secret(value.into())
+    }
+
+    /// The secret value. Avoid storing it in other places, they are not cleared
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Secret {
+        Secret(value)
+    }
+}
+
+impl<'a> From<&'a str> for Secret {
+    fn from(value: &'a str) -> Secret {
+        Secret(value.to_owned())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret({})", REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", REDACTED)
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        // Zero bytes are valid UTF-8, so string stays valid.
+        // Volatile writes are not removed by optimizer even though memory is freed right after
+        unsafe {
+            for byte in self.0.as_mut_vec().iter_mut() {
+                std::ptr::write_volatile(byte, 0);
+            }
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Secret, D::Error> {
+        String::deserialize(deserializer).map(Secret)
+    }
+}
+
+/// Serialize secret value as plain string. Use with `#[serde(serialize_with = "...")]`
+pub fn serialize_exposed<S: Serializer>(secret: &Secret, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(secret.expose())
+}
+
+/// Same as `serialize_exposed` for optional secret
+pub fn serialize_exposed_option<S: Serializer>(secret: &Option<Secret>, serializer: S) -> Result<S::Ok, S::Error> {
+    match *secret {
+        Some(ref secret) => serializer.serialize_some(secret.expose()),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::secret::Secret;
+
+    #[test]
+    fn secret_is_not_shown_test() {
+        let secret = Secret::new("key123");
+        assert_eq!(secret.expose(), "key123");
+        assert_eq!(format!("{}", secret), "<redacted>");
+        assert!(!format!("{:?}", Some(secret.clone())).contains("key123"));
+
+        let deserialized: Secret = serde_json::from_str(r#""key123""#).unwrap();
+        assert_eq!(deserialized, secret);
+    }
+}
@@ -0,0 +1,325 @@
+use futures::{future, Future, Stream};
+use reqwest::r#async::Client as AsyncClient;
+
+use crate::error::WsdError;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::Mutex;
+
+/// Represent response of API received by transport
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportResponse {
+    /// HTTP status code
+    pub status: u16,
+
+    /// Headers of the response. Use `header` to look up value by name
+    pub headers: Vec<(String, String)>,
+
+    pub body: Vec<u8>,
+}
+
+impl TransportResponse {
+    /// Response with given status and body without headers
+    pub fn new(status: u16, body: &[u8]) -> TransportResponse {
+        TransportResponse {
+            status,
+            headers: vec![],
+            body: body.to_vec(),
+        }
+    }
+
+    /// Value of the header. Name is case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Represent response which body is read as it arrives. See `Transport::get_streaming`
+pub struct StreamingResponse {
+    /// HTTP status code
+    pub status: u16,
+
+    /// Headers of the response. Use `header` to look up value by name
+    pub headers: Vec<(String, String)>,
+
+    pub body: Box<dyn Read + Send>,
+}
+
+impl StreamingResponse {
+    /// Value of the header. Name is case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+/// Response of transport which is received without blocking
+pub type TransportFuture = Box<dyn Future<Item=TransportResponse, Error=WsdError> + Send>;
+
+/// HTTP layer used by `WsdClient`.
+///
+/// Errors should be `WsdError::Network` or `WsdError::Io`, so they can be retried
+/// according to `RetryPolicy`. Unsuccessful HTTP status is not an error of transport,
+/// it is handled by the client.
+///
+/// Async methods of the client use `post_form_async` and `get_async`. By default they
+/// perform requests synchronously with `post_form` and `get`
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Send form as `application/x-www-form-urlencoded` POST request
+    fn post_form(&self, url: &str, form: &[(String, String)]) -> Result<TransportResponse, WsdError>;
+
+    /// Perform GET request
+    fn get(&self, url: &str) -> Result<TransportResponse, WsdError>;
+
+    /// Perform GET request without reading the body. It is used to download diagrams
+    /// without holding them in memory. By default body is read by `get`
+    fn get_streaming(&self, url: &str) -> Result<StreamingResponse, WsdError> {
+        let response = self.get(url)?;
+        Ok(StreamingResponse {
+            status: response.status,
+            headers: response.headers,
+            body: Box::new(io::Cursor::new(response.body)),
+        })
+    }
+
+    /// Send form without blocking. By default request is performed by `post_form`
+    fn post_form_async(&self, url: &str, form: &[(String, String)]) -> TransportFuture {
+        Box::new(future::result(self.post_form(url, form)))
+    }
+
+    /// Perform GET request without blocking. By default request is performed by `get`
+    fn get_async(&self, url: &str) -> TransportFuture {
+        Box::new(future::result(self.get(url)))
+    }
+}
+
+/// Default transport based on `reqwest`
+#[derive(Debug, Clone)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+    // None if async requests are performed by blocking client
+    async_client: Option<AsyncClient>,
+}
+
+impl ReqwestTransport {
+    /// Transport with blocking client. Async requests are performed synchronously
+    pub fn new(client: reqwest::Client) -> ReqwestTransport {
+        ReqwestTransport {
+            client,
+            async_client: None,
+        }
+    }
+
+    /// Transport with blocking client and non-blocking one for async requests.
+    /// Both clients should have the same connection options
+    pub fn with_async_client(client: reqwest::Client, async_client: AsyncClient) -> ReqwestTransport {
+        ReqwestTransport {
+            client,
+            async_client: Some(async_client),
+        }
+    }
+
+    fn send(&self, url: &str, request: reqwest::RequestBuilder) -> Result<reqwest::Response, WsdError> {
+        request
+            .send()
+            .map_err(|err| WsdError::Network { url: url.to_owned(), source: err })
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn post_form(&self, url: &str, form: &[(String, String)]) -> Result<TransportResponse, WsdError> {
+        let response = self.send(url, self.client.post(url).form(form))?;
+        read_response(url, response)
+    }
+
+    fn get(&self, url: &str) -> Result<TransportResponse, WsdError> {
+        let response = self.send(url, self.client.get(url))?;
+        read_response(url, response)
+    }
+
+    fn get_streaming(&self, url: &str) -> Result<StreamingResponse, WsdError> {
+        let response = self.send(url, self.client.get(url))?;
+        Ok(StreamingResponse {
+            status: response.status().as_u16(),
+            headers: headers(response.headers()),
+            body: Box::new(response),
+        })
+    }
+
+    fn post_form_async(&self, url: &str, form: &[(String, String)]) -> TransportFuture {
+        match self.async_client {
+            Some(ref async_client) => Box::new(read_response_async(url.to_owned(), async_client.post(url).form(form).send())),
+            None => Box::new(future::result(self.post_form(url, form))),
+        }
+    }
+
+    fn get_async(&self, url: &str) -> TransportFuture {
+        match self.async_client {
+            Some(ref async_client) => Box::new(read_response_async(url.to_owned(), async_client.get(url).send())),
+            None => Box::new(future::result(self.get(url))),
+        }
+    }
+}
+
+fn read_response(url: &str, mut response: reqwest::Response) -> Result<TransportResponse, WsdError> {
+    let mut body = vec![];
+    io::copy(&mut response, &mut body)
+        .map_err(|err| WsdError::Io { url: url.to_owned(), source: err })?;
+    Ok(TransportResponse {
+        status: response.status().as_u16(),
+        headers: headers(response.headers()),
+        body,
+    })
+}
+
+fn read_response_async<F>(url: String, response: F) -> impl Future<Item=TransportResponse, Error=WsdError>
+    where
+        F: Future<Item=reqwest::r#async::Response, Error=reqwest::Error>,
+{
+    let network_error = |url: String| move |err| WsdError::Network { url, source: err };
+    response
+        .map_err(network_error(url.clone()))
+        .and_then(move |response| {
+            let status = response.status().as_u16();
+            let headers = headers(response.headers());
+            response
+                .into_body()
+                .concat2()
+                .map(move |body| TransportResponse {
+                    status,
+                    headers,
+                    body: body.to_vec(),
+                })
+                .map_err(network_error(url))
+        })
+}
+
+fn headers(headers: &reqwest::header::HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| value.to_str().ok().map(|value| (name.as_str().to_owned(), value.to_owned())))
+        .collect()
+}
+
+/// Represent request received by `InMemoryTransport`.
+/// API key is not shown in debug output
+#[derive(Clone, PartialEq, Eq)]
+pub struct TransportRequest {
+    /// HTTP method. E.g. "POST"
+    pub method: String,
+
+    /// Absolute url of the request
+    pub url: String,
+
+    /// Form fields. Empty for GET requests
+    pub form: Vec<(String, String)>,
+}
+
+impl fmt::Debug for TransportRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TransportRequest")
+            .field("method", &self.method)
+            .field("url", &self.url)
+            .field("form", &redacted_form(&self.form))
+            .finish()
+    }
+}
+
+/// Transport which answers requests with prepared responses in order. No network is used.
+///
+/// It is intended for tests:
+/// ```
+/// use std::sync::Arc;
+/// use wsdclient::WsdClient;
+/// use wsdclient::transport::{InMemoryTransport, TransportResponse};
+///
+/// let transport = Arc::new(InMemoryTransport::new());
+/// transport.push_response(TransportResponse::new(200, br#"{"img": "?png=mscKTO107", "errors": []}"#));
+/// transport.push_response(TransportResponse::new(200, b"PNG DATA"));
+/// let client = WsdClient::builder().transport(transport.clone()).build().unwrap();
+/// let rez = client.get_diagram_with_defaults("A->B: text").unwrap();
+/// assert_eq!(rez.diagram, b"PNG DATA".to_vec());
+/// assert_eq!(transport.requests().len(), 2);
+/// ```
+#[derive(Debug, Default)]
+pub struct InMemoryTransport {
+    responses: Mutex<VecDeque<Result<TransportResponse, io::ErrorKind>>>,
+    requests: Mutex<Vec<TransportRequest>>,
+}
+
+impl InMemoryTransport {
+    pub fn new() -> InMemoryTransport {
+        InMemoryTransport::default()
+    }
+
+    /// Add response to the queue
+    pub fn push_response(&self, response: TransportResponse) {
+        lock(&self.responses).push_back(Ok(response));
+    }
+
+    /// Add IO error to the queue. E.g. `ConnectionReset`
+    pub fn push_error(&self, kind: io::ErrorKind) {
+        lock(&self.responses).push_back(Err(kind));
+    }
+
+    /// All requests received so far
+    pub fn requests(&self) -> Vec<TransportRequest> {
+        lock(&self.requests).clone()
+    }
+
+    fn respond(&self, method: &str, url: &str, form: &[(String, String)]) -> Result<TransportResponse, WsdError> {
+        lock(&self.requests).push(TransportRequest {
+            method: method.to_owned(),
+            url: url.to_owned(),
+            form: form.to_vec(),
+        });
+        let io_error = |kind: io::ErrorKind| WsdError::Io {
+            url: url.to_owned(),
+            source: kind.into(),
+        };
+        match lock(&self.responses).pop_front() {
+            Some(Ok(response)) => Ok(response),
+            Some(Err(kind)) => Err(io_error(kind)),
+            // Like server which closes connection without response
+            None => Err(io_error(io::ErrorKind::UnexpectedEof)),
+        }
+    }
+}
+
+impl Transport for InMemoryTransport {
+    fn post_form(&self, url: &str, form: &[(String, String)]) -> Result<TransportResponse, WsdError> {
+        self.respond("POST", url, form)
+    }
+
+    fn get(&self, url: &str) -> Result<TransportResponse, WsdError> {
+        self.respond("GET", url, &[])
+    }
+}
+
+// Form fields with value of API key replaced, so they can be logged
+pub(crate) fn redacted_form(form: &[(String, String)]) -> Vec<(&str, &str)> {
+    form
+        .iter()
+        .map(|(name, value)| if name == "apikey" {
+            (name.as_str(), "<redacted>")
+        } else {
+            (name.as_str(), value.as_str())
+        })
+        .collect()
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
@@ -2,8 +2,10 @@ use serde::{Serialize, Deserialize};
 
 use regex::Regex;
 
-use std::error::Error;
+use crate::error::WsdError;
+use crate::secret::Secret;
 
+use std::fmt;
 use std::str::FromStr;
 
 fn normalise_str(s: &str) -> String {
@@ -29,12 +31,7 @@ pub trait WSDEnum
     }
 
     fn from_str(s: &str) -> Option<Self> {
-        for x in Self::all() {
-            if normalise_str(&x.human_readable_value()) == normalise_str(s) {
-                return Some(x);
-            }
-        }
-        None
+        Self::all().into_iter().find(|x| normalise_str(&x.human_readable_value()) == normalise_str(s))
     }
 
     fn all_wsd_values() -> Vec<String> {
@@ -247,7 +244,7 @@ impl WSDEnum for PaperOrientation {
 }
 
 /// represent parameters for plotting
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PlotParameters {
     /// style to used. By default `default` style is used
     pub style: Style,
@@ -265,18 +262,87 @@ pub struct PlotParameters {
     pub scale: Option<u32>,
 
     /// API key. Some features requires premium account.
-    pub api_key: Option<String>,
+    /// It is not shown by `Debug` and is skipped on serialization
+    #[serde(default, skip_serializing)]
+    pub api_key: Option<Secret>,
 }
 
-impl Default for PlotParameters {
-    fn default() -> PlotParameters {
-        PlotParameters {
-            style: Style::default(),
-            format: Format::default(),
-            paper_size: None,
-            paper_orientation: None,
-            scale: None,
-            api_key: None,
+/// Smallest scale accepted by `PlotParameters::validate` without warning
+pub const MIN_SCALE: u32 = 10;
+
+/// Largest scale accepted by `PlotParameters::validate` without warning
+pub const MAX_SCALE: u32 = 400;
+
+impl PlotParameters {
+    /// Checks parameters for combinations which are accepted by API but do not work as expected.
+    /// E.g. pdf format without API key is silently replaced by png.
+    /// Empty result means no problems are found. No request is sent
+    pub fn validate(&self) -> Vec<ParameterWarning> {
+        let mut warnings = vec![];
+        // Empty key (e.g. from empty environment variable) does not unlock premium features
+        let has_api_key = self.api_key.as_ref().map_or(false, |api_key| !api_key.expose().trim().is_empty());
+        if self.format.premium_feature() && !has_api_key {
+            warnings.push(ParameterWarning::PremiumFormatWithoutApiKey { format: self.format.clone() });
+        }
+        if self.format != Format::Pdf {
+            if let Some(ref paper_size) = self.paper_size {
+                warnings.push(ParameterWarning::PaperSizeIgnored {
+                    paper_size: paper_size.clone(),
+                    format: self.format.clone(),
+                });
+            }
+            if let Some(ref paper_orientation) = self.paper_orientation {
+                warnings.push(ParameterWarning::PaperOrientationIgnored {
+                    paper_orientation: paper_orientation.clone(),
+                    format: self.format.clone(),
+                });
+            }
+        }
+        if let Some(scale) = self.scale {
+            if !(MIN_SCALE..=MAX_SCALE).contains(&scale) {
+                warnings.push(ParameterWarning::ScaleOutOfRange { scale });
+            } else if self.format != Format::Png {
+                warnings.push(ParameterWarning::ScaleIgnored { format: self.format.clone() });
+            }
+        }
+        warnings
+    }
+}
+
+/// Represent a problem in plot parameters found by `PlotParameters::validate`.
+/// Diagram can still be requested, but result may differ from expected
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParameterWarning {
+    /// Format requires premium account but API key is not set or empty.
+    /// API returns png instead
+    PremiumFormatWithoutApiKey { format: Format },
+
+    /// Paper size is only used with pdf format
+    PaperSizeIgnored { paper_size: PaperSize, format: Format },
+
+    /// Paper orientation is only used with pdf format
+    PaperOrientationIgnored { paper_orientation: PaperOrientation, format: Format },
+
+    /// Scale is not in `MIN_SCALE..=MAX_SCALE`
+    ScaleOutOfRange { scale: u32 },
+
+    /// Scale is only used with png format
+    ScaleIgnored { format: Format },
+}
+
+impl fmt::Display for ParameterWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParameterWarning::PremiumFormatWithoutApiKey { format } =>
+                write!(f, "format `{}` is a premium feature but API key is not set. png will be returned instead", format.wsd_value()),
+            ParameterWarning::PaperSizeIgnored { paper_size, format } =>
+                write!(f, "paper size `{}` is ignored for `{}` format. It is only used with pdf", paper_size.human_readable_value(), format.wsd_value()),
+            ParameterWarning::PaperOrientationIgnored { paper_orientation, format } =>
+                write!(f, "paper orientation `{}` is ignored for `{}` format. It is only used with pdf", paper_orientation.human_readable_value(), format.wsd_value()),
+            ParameterWarning::ScaleOutOfRange { scale } =>
+                write!(f, "scale {} is out of range. It should be from {} to {}", scale, MIN_SCALE, MAX_SCALE),
+            ParameterWarning::ScaleIgnored { format } =>
+                write!(f, "scale is ignored for `{}` format. It is only used with png", format.wsd_value()),
         }
     }
 }
@@ -305,33 +371,33 @@ pub struct DiagramError {
 
 impl DiagramError {
     // "Line 1: Syntax error."
-    pub fn from_wsd_error_str(error: &str) -> Result<DiagramError, Box<Error>> {
+    pub fn from_wsd_error_str(error: &str) -> Result<DiagramError, WsdError> {
         // TODO(mkl): maybe use lazy_static ?
         let re = Regex::new(r"(?ix)
 \s*Line\s+
 (?P<line_number>\d+)  # the line number
 \s* : \s*
 (?P<description>.*) # the description
-")?;
+").expect("regex for error line is valid");
+
+        let invalid_line = |source| WsdError::InvalidErrorLine { line: error.to_owned(), source };
 
         let caps = if let Some(caps) = re.captures(error) {
             caps
         } else {
-            return Err("Error parsing error response.".into())
+            return Err(invalid_line(None))
         };
         let line_number = if let Some(line_number_match) = caps.name("line_number"){
-            match  i32::from_str(line_number_match.as_str()) {
-                Ok(x) => x,
-                Err(err) => return Err(format!("Error parsing error response. Cannot convert line_number into int: {} . String: `{}`", err, line_number_match.as_str()).into()),
-            }
+            i32::from_str(line_number_match.as_str())
+                .map_err(|err| invalid_line(Some(err)))?
         } else {
-            return Err("Error parsing error response. Group `line_number` not found".into())
+            return Err(invalid_line(None))
         };
 
         let description = if let Some(description_match) = caps.name("description") {
             description_match.as_str().to_owned()
         } else {
-            return Err("Error parsing error response. Group `description` not found".into())
+            return Err(invalid_line(None))
         };
         Ok(DiagramError {
             line_number,
@@ -343,7 +409,8 @@ impl DiagramError {
 
 #[cfg(test)]
 mod tests {
-    use crate::types::DiagramError;
+    use crate::types::{DiagramError, Format, PaperOrientation, PaperSize, ParameterWarning, PlotParameters};
+    use crate::error::WsdError;
 
     #[test]
     fn from_wsd_error_1_test() {
@@ -383,5 +450,55 @@ mod tests {
         }
     }
 
+    #[test]
+    fn from_wsd_error_invalid_test() {
+        match DiagramError::from_wsd_error_str("Syntax error.") {
+            Err(WsdError::InvalidErrorLine { line, .. }) => assert_eq!(line, "Syntax error."),
+            rez => panic!("InvalidErrorLine error expected. Instead got: {:?}", rez),
+        }
+    }
+
+    #[test]
+    fn validate_test() {
+        assert_eq!(PlotParameters::default().validate(), vec![]);
+
+        let pdf = PlotParameters {
+            format: Format::Pdf,
+            paper_size: Some(PaperSize::A4),
+            paper_orientation: Some(PaperOrientation::Landscape),
+            api_key: Some("key".into()),
+            ..PlotParameters::default()
+        };
+        assert_eq!(pdf.validate(), vec![]);
+
+        let svg = PlotParameters {
+            format: Format::Svg,
+            paper_size: Some(PaperSize::A4),
+            paper_orientation: Some(PaperOrientation::Landscape),
+            scale: Some(200),
+            ..PlotParameters::default()
+        };
+        assert_eq!(svg.validate(), vec![
+            ParameterWarning::PremiumFormatWithoutApiKey { format: Format::Svg },
+            ParameterWarning::PaperSizeIgnored { paper_size: PaperSize::A4, format: Format::Svg },
+            ParameterWarning::PaperOrientationIgnored { paper_orientation: PaperOrientation::Landscape, format: Format::Svg },
+            ParameterWarning::ScaleIgnored { format: Format::Svg },
+        ]);
+
+        let empty_key = PlotParameters {
+            api_key: Some(" \n".into()),
+            ..pdf
+        };
+        assert_eq!(empty_key.validate(), vec![ParameterWarning::PremiumFormatWithoutApiKey { format: Format::Pdf }]);
 
-}
\ No newline at end of file
+        let png = PlotParameters {
+            scale: Some(0),
+            ..PlotParameters::default()
+        };
+        assert_eq!(png.validate(), vec![ParameterWarning::ScaleOutOfRange { scale: 0 }]);
+        assert_eq!(
+            png.validate()[0].to_string(),
+            "scale 0 is out of range. It should be from 10 to 400"
+        );
+    }
+}
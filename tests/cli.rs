@@ -0,0 +1,141 @@
+extern crate wsdclient;
+
+use wsdclient::mock_server::{MockBehavior, MockServer};
+use wsdclient::{Fixtures, Format, PlotParameters, RateLimiter, RetryPolicy, WsdClient};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::Arc;
+
+const SAMPLE_DIAGRAMS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/sample_diagrams");
+const MOCK_FIXTURES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/mock_fixtures");
+
+// Errors which mock server reports for sample diagrams. They imitate errors of API
+fn mock_errors(name: &str) -> Vec<String> {
+    match name {
+        "02_errors_in_diag" => vec![
+            "Line 1: Syntax error.".to_owned(),
+            "Line 3: Deactivate: A was not activated.".to_owned(),
+        ],
+        _ => vec![],
+    }
+}
+
+// Names without extension and paths of sample diagrams
+fn sample_diagrams() -> Vec<(String, PathBuf)> {
+    let mut diagrams = fs::read_dir(SAMPLE_DIAGRAMS_DIR)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |extension| extension == "wsd"))
+        .map(|path| (path.file_stem().unwrap().to_string_lossy().into_owned(), path))
+        .collect::<Vec<_>>();
+    diagrams.sort();
+    diagrams
+}
+
+// Copies sample diagrams into empty directory, so output files and config files of the repository
+// do not interfere
+fn copy_sample_diagrams(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("wsdclient-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (_, path) in sample_diagrams() {
+        fs::copy(&path, dir.join(path.file_name().unwrap())).unwrap();
+    }
+    dir
+}
+
+// Cargo builds binaries of the package for integration tests into the directory of `deps`
+// directory with test executables. `CARGO_BIN_EXE_*` is not used, it needs newer cargo than
+// rust-version of the crate
+fn wsdclient_binary() -> PathBuf {
+    let mut dir = std::env::current_exe().unwrap();
+    dir.pop();
+    if dir.ends_with("deps") {
+        dir.pop();
+    }
+    dir.join(format!("wsdclient{}", std::env::consts::EXE_SUFFIX))
+}
+
+// Runs wsdclient with user config and cache directories inside `dir`
+fn wsdclient(dir: &Path, args: &[&str]) -> Output {
+    Command::new(wsdclient_binary())
+        .args(args)
+        .current_dir(dir)
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .env_remove("WEBSEQUENCEDIAGRAM_API_KEY")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn replay_mock_fixtures_test() {
+    let dir = copy_sample_diagrams("replay");
+    let mut args = vec!["--replay-fixtures", MOCK_FIXTURES_DIR];
+    let inputs = sample_diagrams()
+        .into_iter()
+        .map(|(name, _)| format!("{}.wsd", name))
+        .collect::<Vec<_>>();
+    args.extend(inputs.iter().map(String::as_str));
+
+    let output = wsdclient(&dir, &args);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("02_errors_in_diag.wsd:1 : Syntax error."));
+    assert!(stderr.contains("02_errors_in_diag.wsd:3 : Deactivate: A was not activated."));
+    for (name, path) in sample_diagrams() {
+        let spec = fs::read_to_string(&path).unwrap();
+        assert_eq!(fs::read(dir.join(format!("{}.png", name))).unwrap(), MockServer::image(&Format::Png, &spec));
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn replay_fatal_errors_test() {
+    let dir = copy_sample_diagrams("fatal");
+    let output = wsdclient(&dir, &["--replay-fixtures", MOCK_FIXTURES_DIR, "--errors-fatal", "02_errors_in_diag.wsd"]);
+    assert!(!output.status.success());
+    // Diagram with fatal errors is not kept
+    assert!(!dir.join("02_errors_in_diag.png").exists());
+    assert!(!dir.join("02_errors_in_diag.png.part").exists());
+
+    fs::write(dir.join("04_unrecorded.wsd"), "A->B: not recorded").unwrap();
+    let output = wsdclient(&dir, &["--replay-fixtures", MOCK_FIXTURES_DIR, "04_unrecorded.wsd"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("was not recorded"));
+    assert!(!dir.join("04_unrecorded.png").exists());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+// Fixtures of sample diagrams are recorded against mock server, so they do not change between runs.
+// Run `cargo test --test cli -- --ignored` after changing sample diagrams
+#[test]
+#[ignore]
+fn record_mock_fixtures() {
+    // Fixtures of removed or changed diagrams are not kept
+    for entry in fs::read_dir(MOCK_FIXTURES_DIR).into_iter().flatten() {
+        let path = entry.unwrap().path();
+        if path.extension().map_or(false, |extension| extension == "json" || extension == "body") {
+            fs::remove_file(path).unwrap();
+        }
+    }
+    let server = MockServer::start();
+    let client = WsdClient::builder()
+        .base_url(server.base_url())
+        .retry_policy(RetryPolicy::none())
+        .rate_limiter(Arc::new(RateLimiter::new(None)))
+        .fixtures(Fixtures::record(MOCK_FIXTURES_DIR))
+        .build()
+        .unwrap();
+    for (name, path) in sample_diagrams() {
+        server.set_behavior(MockBehavior {
+            errors: mock_errors(&name),
+            ..MockBehavior::default()
+        });
+        let spec = fs::read_to_string(&path).unwrap();
+        client.get_diagram(&spec, &PlotParameters::default()).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+extern crate wsdclient;
+extern crate tokio;
+
+use wsdclient::mock_server::{MockBehavior, MockServer};
+use wsdclient::{CacheOptions, Fixtures, Format, PlotParameters, RetryPolicy, WsdClient, WsdError};
+
+use std::path::PathBuf;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("wsdclient-fixtures-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn client(base_url: &str, fixtures: Fixtures) -> WsdClient {
+    WsdClient::builder()
+        .base_url(base_url)
+        .retry_policy(RetryPolicy::none())
+        .fixtures(fixtures)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn record_replay_test() {
+    let dir = temp_dir("record-replay");
+    let server = MockServer::with_behavior(MockBehavior {
+        errors: vec!["Line 1: Syntax error.".to_owned()],
+        ..MockBehavior::default()
+    });
+    let parameters = PlotParameters {
+        api_key: Some("secret".into()),
+        ..PlotParameters::default()
+    };
+    let recorded = client(server.base_url(), Fixtures::record(&dir))
+        .get_diagram("A->B: text", &parameters)
+        .unwrap();
+    assert_eq!(server.requests().len(), 2);
+    drop(server);
+
+    // Nothing listens on this port, so responses can only come from fixtures
+    let replaying = client("http://127.0.0.1:1", Fixtures::replay(&dir));
+    let replayed = replaying.get_diagram("A->B: text", &parameters).unwrap();
+    assert_eq!(replayed.diagram, MockServer::image(&Format::Png, "A->B: text"));
+    assert_eq!(replayed.diagram, recorded.diagram);
+    assert_eq!(replayed.errors, recorded.errors);
+    assert_eq!(replayed.response, recorded.response);
+
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let replayed_async = runtime
+        .block_on(replaying.get_diagram_async("A->B: text", &parameters))
+        .unwrap();
+    assert_eq!(replayed_async.diagram, recorded.diagram);
+
+    match replaying.get_diagram("A->B: other text", &parameters) {
+        Err(WsdError::UnrecordedRequest { method, path, .. }) => {
+            assert_eq!(method, "POST");
+            assert_eq!(path, "/index.php");
+        }
+        _ => panic!("expected unrecorded request"),
+    }
+
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        let content = std::fs::read(entry.unwrap().path()).unwrap();
+        assert!(!String::from_utf8_lossy(&content).contains("secret"));
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn replay_errors_test() {
+    let dir = temp_dir("errors");
+    let server = MockServer::start();
+    server.fail_next_message(500, "Internal Server Error");
+    let err = client(server.base_url(), Fixtures::record(&dir))
+        .get_diagram_with_defaults("A->B: text")
+        .err()
+        .unwrap();
+    assert_eq!(err.status(), Some(500));
+
+    let err = client("http://127.0.0.1:1", Fixtures::replay(&dir))
+        .get_diagram_with_defaults("A->B: text")
+        .err()
+        .unwrap();
+    assert_eq!(err.status(), Some(500));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn fixtures_disable_cache_test() {
+    let dir = temp_dir("cache");
+    let server = MockServer::start();
+    let recording = WsdClient::builder()
+        .base_url(server.base_url())
+        .cache(CacheOptions::new(dir.join("cache")))
+        .fixtures(Fixtures::record(dir.join("fixtures")))
+        .build()
+        .unwrap();
+    assert!(recording.cache().is_none());
+    recording.get_diagram_with_defaults("A->B: text").unwrap();
+    recording.get_diagram_with_defaults("A->B: text").unwrap();
+    // Second diagram is not taken from cache, so it is requested (and recorded) again
+    assert_eq!(server.requests().len(), 4);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,31 @@
+extern crate wsdclient;
+
+use wsdclient::mock_server::MockServer;
+use wsdclient::{lint, wsd, PlotParameters, WsdClient};
+
+const SPEC: &str = wsd!(r#"
+title Login
+participant "Web Server" as S
+User->+S: POST /login
+alt valid password
+    S-->User: 302 Found
+else
+    note right of S: wrong password
+    S-->User: 401 Unauthorized
+end
+deactivate S
+"#);
+
+#[test]
+fn wsd_macro_test() {
+    assert!(SPEC.starts_with("\ntitle Login\n"));
+    assert!(lint(SPEC).is_empty());
+
+    let server = MockServer::start();
+    let client = WsdClient::builder()
+        .base_url(server.base_url())
+        .build()
+        .unwrap();
+    let result = client.get_diagram(wsd!("A->B: text"), &PlotParameters::default()).unwrap();
+    assert!(result.errors.is_empty());
+}
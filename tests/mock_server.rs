@@ -0,0 +1,216 @@
+extern crate wsdclient;
+extern crate tokio;
+
+use wsdclient::mock_server::{MockBehavior, MockServer};
+use wsdclient::{
+    BatchOptions, DiagramError, Format, PlotParameters, RateLimit, RateLimiter, RetryPolicy, Secret, WsdClient,
+    WsdError,
+};
+
+use std::fs::{self, File};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn client(server: &MockServer) -> WsdClient {
+    WsdClient::builder()
+        .base_url(server.base_url())
+        .retry_policy(RetryPolicy::none())
+        // Own limiter, so pauses after 429 do not affect other tests
+        .rate_limiter(Arc::new(RateLimiter::new(None)))
+        .build()
+        .unwrap()
+}
+
+fn pdf_parameters(api_key: Option<&str>) -> PlotParameters {
+    PlotParameters {
+        format: Format::Pdf,
+        api_key: api_key.map(Secret::from),
+        ..PlotParameters::default()
+    }
+}
+
+#[test]
+fn get_diagram_test() {
+    let server = MockServer::start();
+    let rez = client(&server).get_diagram_with_defaults("A->B: text").unwrap();
+    assert_eq!(rez.diagram, MockServer::image(&Format::Png, "A->B: text"));
+    assert_eq!(rez.actual_format, Format::Png);
+    assert!(rez.errors.is_empty());
+    assert_eq!(rez.response.num_pages, Some(1));
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].form_value("message"), Some("A->B: text"));
+    assert_eq!(requests[0].form_value("format"), Some("png"));
+    assert_eq!(requests[0].form_value("apiVersion"), Some("1"));
+    assert_eq!(requests[1].method, "GET");
+    assert_eq!(requests[1].path, format!("/index.php{}", rez.response.img));
+}
+
+#[test]
+fn diagram_errors_test() {
+    let server = MockServer::with_behavior(MockBehavior {
+        errors: vec!["Line 2: Syntax error.".to_owned()],
+        ..MockBehavior::default()
+    });
+    let rez = client(&server).get_diagram_with_defaults("A->B: text\nA-B").unwrap();
+    assert_eq!(rez.errors, vec![DiagramError::from_wsd_error_str("Line 2: Syntax error.").unwrap()]);
+}
+
+#[test]
+fn premium_format_downgrade_test() {
+    let server = MockServer::with_behavior(MockBehavior {
+        api_key: Some("secret".to_owned()),
+        ..MockBehavior::default()
+    });
+    let client = client(&server);
+
+    let rez = client.get_diagram("A->B: text", &pdf_parameters(None)).unwrap();
+    assert_eq!(rez.actual_format, Format::Png);
+    let rez = client.get_diagram("A->B: text", &pdf_parameters(Some("wrong"))).unwrap();
+    assert_eq!(rez.actual_format, Format::Png);
+    let rez = client.get_diagram("A->B: text", &pdf_parameters(Some("secret"))).unwrap();
+    assert_eq!(rez.actual_format, Format::Pdf);
+    assert_eq!(rez.diagram, MockServer::image(&Format::Pdf, "A->B: text"));
+}
+
+#[test]
+fn image_token_test() {
+    let server = MockServer::with_behavior(MockBehavior {
+        image_token: Some("mscKTO107".to_owned()),
+        ..MockBehavior::default()
+    });
+    let ticket = client(&server).submit("A->B: text", &PlotParameters::default()).unwrap();
+    assert_eq!(ticket.image_path, "?png=mscKTO107");
+    assert_eq!(ticket.url(), format!("{}/index.php?png=mscKTO107", server.base_url()));
+}
+
+#[test]
+fn http_errors_test() {
+    let server = MockServer::start();
+    let client = client(&server);
+
+    server.fail_next_message(500, "Internal Server Error");
+    match client.get_diagram_with_defaults("A->B: text") {
+        Err(WsdError::Status { status, body, .. }) => {
+            assert_eq!(status, 500);
+            assert_eq!(body, "Internal Server Error");
+        }
+        _ => panic!("expected status error"),
+    }
+
+    server.fail_next_image(404, "Not Found");
+    let err = client.get_diagram_with_defaults("A->B: text").err().unwrap();
+    assert_eq!(err.status(), Some(404));
+
+    server.fail_next_message_with_headers(429, &[("Retry-After", "0")], "Too Many Requests");
+    match client.get_diagram_with_defaults("A->B: text") {
+        Err(WsdError::RateLimited { retry_after, .. }) => assert_eq!(retry_after, Some(Duration::from_secs(0))),
+        _ => panic!("expected rate limited error"),
+    }
+}
+
+#[test]
+fn retry_test() {
+    let server = MockServer::start();
+    let client = WsdClient::builder()
+        .base_url(server.base_url())
+        .retry_policy(RetryPolicy {
+            backoff_base: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        })
+        .build()
+        .unwrap();
+    server.fail_next_message(503, "Service Unavailable");
+    server.fail_next_image(502, "Bad Gateway");
+    let rez = client.get_diagram_with_defaults("A->B: text").unwrap();
+    assert_eq!(rez.message_attempts, 2);
+    assert_eq!(rez.image_attempts, 2);
+    assert_eq!(server.message_requests_count(), 2);
+}
+
+#[test]
+fn slow_response_timeout_test() {
+    let server = MockServer::with_behavior(MockBehavior {
+        delay: Duration::from_millis(500),
+        ..MockBehavior::default()
+    });
+    let client = WsdClient::builder()
+        .base_url(server.base_url())
+        .retry_policy(RetryPolicy::none())
+        .timeout(Duration::from_millis(100))
+        .build()
+        .unwrap();
+    match client.get_diagram_with_defaults("A->B: text") {
+        Err(WsdError::Network { source, .. }) => assert!(source.is_timeout()),
+        _ => panic!("expected timeout"),
+    }
+}
+
+#[test]
+fn render_to_writer_test() {
+    let server = MockServer::start();
+    let mut output = vec![];
+    let rez = client(&server)
+        .render_to_writer("A->B: text", &PlotParameters::default(), &mut output)
+        .unwrap();
+    assert_eq!(output, MockServer::image(&Format::Png, "A->B: text"));
+    assert_eq!(rez.bytes_written, output.len() as u64);
+}
+
+#[test]
+fn get_diagram_async_test() {
+    let server = MockServer::start();
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let rez = runtime
+        .block_on(client(&server).get_diagram_async("A->B: text", &PlotParameters::default()))
+        .unwrap();
+    assert_eq!(rez.diagram, MockServer::image(&Format::Png, "A->B: text"));
+}
+
+#[test]
+fn render_batch_test() {
+    let server = MockServer::start();
+    server.fail_next_message(400, "Bad Request");
+    let jobs = (0..5).map(|i| (i, format!("A->B: diagram {}", i), PlotParameters::default()));
+    let results = client(&server).render_batch(jobs, &BatchOptions { concurrency: 1 });
+    assert_eq!(results.len(), 5);
+    assert_eq!(results[0].result.as_ref().err().and_then(|err| err.status()), Some(400));
+    for (i, batch_result) in results.iter().enumerate().skip(1) {
+        assert_eq!(batch_result.id, i);
+        let rez = batch_result.result.as_ref().unwrap();
+        assert_eq!(rez.diagram, MockServer::image(&Format::Png, &format!("A->B: diagram {}", i)));
+    }
+}
+
+#[test]
+fn render_batch_to_writers_test() {
+    let server = MockServer::start();
+    let dir = std::env::temp_dir().join(format!("wsdclient-batch-writers-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // Both requests of every job take token of the client's limiter
+    let client = WsdClient::builder()
+        .base_url(server.base_url())
+        .retry_policy(RetryPolicy::none())
+        .rate_limiter(Arc::new(RateLimiter::new(Some(RateLimit::per_second(20.0)))))
+        .build()
+        .unwrap();
+    let jobs = (0..2).map(|i| {
+        let file = File::create(dir.join(format!("{}.png", i))).unwrap();
+        (i, format!("A->B: diagram {}", i), PlotParameters::default(), file)
+    });
+    let started = Instant::now();
+    let results = client.render_batch_to_writers(jobs, &BatchOptions { concurrency: 2 });
+    assert!(started.elapsed() >= Duration::from_millis(140));
+
+    for (i, batch_result) in results.iter().enumerate() {
+        let expected = MockServer::image(&Format::Png, &format!("A->B: diagram {}", i));
+        let rez = batch_result.result.as_ref().unwrap();
+        assert_eq!(rez.bytes_written, expected.len() as u64);
+        assert_eq!(rez.actual_format, Format::Png);
+        assert_eq!(fs::read(dir.join(format!("{}.png", i))).unwrap(), expected);
+    }
+    assert_eq!(server.requests().len(), 4);
+    let _ = fs::remove_dir_all(&dir);
+}
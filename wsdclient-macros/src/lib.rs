@@ -0,0 +1,72 @@
+//! Procedural macros for [wsdclient](https://crates.io/crates/wsdclient).
+//!
+//! Use them through `wsdclient` with `macros` feature: `wsdclient::wsd!`.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, LitStr};
+// Parser is shared with wsdclient crate, so macro accepts exactly the same diagrams
+use wsdclient_syntax::{self as syntax, SyntaxError};
+
+/// Check diagram specification at compile time. Expands to the same `&'static str`,
+/// so it can be passed to `get_diagram`.
+///
+/// Syntax errors, unclosed blocks, unbalanced activations, unknown participants in notes
+/// and duplicate aliases are compile errors (the same checks as `wsdclient::lint`).
+/// Error points to the line of the diagram if compiler supports spans inside literals,
+/// otherwise to the whole literal. Line and column are also in the message.
+/// ```
+/// let spec: &str = wsdclient_macros::wsd!("A->+B: request\nB-->-A: response");
+/// assert_eq!(spec, "A->+B: request\nB-->-A: response");
+/// ```
+///
+/// ```compile_fail
+/// let spec: &str = wsdclient_macros::wsd!("alt ok\n    A->B: request");
+/// ```
+#[proc_macro]
+pub fn wsd(input: TokenStream) -> TokenStream {
+    let literal = parse_macro_input!(input as LitStr);
+    let spec = literal.value();
+    let (diagram, mut errors) = syntax::parse_with_errors(&spec);
+    errors.extend(diagram.check());
+    errors.sort_by_key(|error| error.span.start);
+
+    let compile_error = errors
+        .iter()
+        .map(|error| syn::Error::new(error_span(&literal, &spec, error), format!("invalid diagram: {} (column {})", error, error.span.column)))
+        .fold(None, |combined: Option<syn::Error>, error| match combined {
+            Some(mut combined) => {
+                combined.combine(error);
+                Some(combined)
+            }
+            None => Some(error),
+        });
+    match compile_error {
+        // Block is needed to report several errors in expression position
+        Some(compile_error) => {
+            let compile_error = compile_error.to_compile_error();
+            quote!({ #compile_error #literal }).into()
+        }
+        None => quote!(#literal).into(),
+    }
+}
+
+// Span of the error inside the literal. Offsets in the value are the same as in the source
+// only if literal has no escapes (e.g. raw string)
+fn error_span(literal: &LitStr, spec: &str, error: &SyntaxError) -> proc_macro2::Span {
+    let token = literal.token();
+    let source = token.to_string();
+    let value_start = match source.find('"') {
+        Some(quote) => quote + 1,
+        None => return literal.span(),
+    };
+    if source.get(value_start..value_start + spec.len()) != Some(spec) {
+        return literal.span();
+    }
+    let end = error.span.end.max(error.span.start + 1).min(spec.len());
+    token
+        .subspan(value_start + error.span.start..value_start + end)
+        .unwrap_or_else(|| literal.span())
+}
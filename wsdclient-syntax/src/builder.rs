@@ -0,0 +1,335 @@
+use crate::{
+    Activation, Arrow, ArrowHead, Block, BlockKind, Diagram, ElseBranch, LineStyle, Name,
+    NotePosition, ParticipantKind, Signal, Span, Statement, StatementKind,
+};
+
+/// Builder of diagram specification. Text of the diagram is obtained with `to_string()`.
+///
+/// Names and texts are escaped: line breaks are written as `\n` which is shown by API as
+/// line break, names which cannot be written as is are quoted. Quoted names cannot contain `"`,
+/// so `"` in names is replaced with `'`. Empty names are not allowed by wsd, so empty and
+/// whitespace-only names are replaced with `EMPTY_NAME`.
+/// ```
+/// use wsdclient_syntax::{Arrow, Diagram, NotePosition};
+///
+/// let diagram = Diagram::new()
+///     .participant_as("Web Server", "S")
+///     .message("User", "S", "GET /: index")
+///     .activate("S")
+///     .signal("S", Arrow::reply(), "User", "200 OK\nindex.html")
+///     .deactivate("S")
+///     .note(NotePosition::RightOf, "S", "cached")
+///     .alt("logged in", |b| b.message("S", "User", "profile"))
+///     .else_branch("anonymous", |b| b.message("S", "User", "login form"))
+///     .end();
+/// assert_eq!(diagram.to_string(), "\
+/// participant Web Server as S
+/// User->S: GET /: index
+/// activate S
+/// S-->User: 200 OK\\nindex.html
+/// deactivate S
+/// note right of S: cached
+/// alt logged in
+///     S->User: profile
+/// else anonymous
+///     S->User: login form
+/// end
+/// ");
+/// ```
+impl Diagram {
+    pub fn new() -> Diagram {
+        Diagram::default()
+    }
+
+    /// `title text`
+    pub fn title(self, text: &str) -> Diagram {
+        self.push(StatementKind::Title(escape_text(text)))
+    }
+
+    /// `participant name`. Participants are shown in order of declaration.
+    /// `"` in name is replaced with `'`
+    pub fn participant(self, name: &str) -> Diagram {
+        self.push(StatementKind::Participant {
+            kind: ParticipantKind::Participant,
+            name: self::name(name),
+            alias: None,
+        })
+    }
+
+    /// `participant "long name" as alias`. Alias is used in other statements.
+    /// `"` in name and alias is replaced with `'`
+    pub fn participant_as(self, name: &str, alias: &str) -> Diagram {
+        self.push(StatementKind::Participant {
+            kind: ParticipantKind::Participant,
+            name: self::name(name),
+            alias: Some(self::name(alias)),
+        })
+    }
+
+    /// `actor name`. `"` in name is replaced with `'`
+    pub fn actor(self, name: &str) -> Diagram {
+        self.push(StatementKind::Participant {
+            kind: ParticipantKind::Actor,
+            name: self::name(name),
+            alias: None,
+        })
+    }
+
+    /// `from->to: text`. `"` in names is replaced with `'`
+    pub fn message(self, from: &str, to: &str, text: &str) -> Diagram {
+        self.signal(from, Arrow::default(), to, text)
+    }
+
+    /// Signal with given arrow. E.g. `Arrow::reply()` is `from-->to: text`.
+    /// `"` in names is replaced with `'`
+    pub fn signal(self, from: &str, arrow: Arrow, to: &str, text: &str) -> Diagram {
+        self.push(StatementKind::Signal(Signal {
+            from: name(from),
+            arrow,
+            to: name(to),
+            text: escape_text(text),
+        }))
+    }
+
+    /// `activate name`. `"` in name is replaced with `'`
+    pub fn activate(self, name: &str) -> Diagram {
+        self.push(StatementKind::Activate(self::name(name)))
+    }
+
+    /// `deactivate name`. `"` in name is replaced with `'`
+    pub fn deactivate(self, name: &str) -> Diagram {
+        self.push(StatementKind::Deactivate(self::name(name)))
+    }
+
+    /// `note left of name: text`, `note right of name: text` or `note over name: text`.
+    /// `"` in name is replaced with `'`
+    pub fn note(self, position: NotePosition, name: &str, text: &str) -> Diagram {
+        self.push(StatementKind::Note {
+            position,
+            participants: vec![self::name(name)],
+            text: escape_text(text),
+        })
+    }
+
+    /// `note over A,B: text`. Note spans all given participants.
+    /// `"` in names is replaced with `'`
+    pub fn note_over(self, names: &[&str], text: &str) -> Diagram {
+        self.push(StatementKind::Note {
+            position: NotePosition::Over,
+            participants: names.iter().map(|name| self::name(name)).collect(),
+            text: escape_text(text),
+        })
+    }
+
+    /// `state over A,B: text`. `"` in names is replaced with `'`
+    pub fn state_over(self, names: &[&str], text: &str) -> Diagram {
+        self.push(StatementKind::State {
+            participants: names.iter().map(|name| self::name(name)).collect(),
+            text: escape_text(text),
+        })
+    }
+
+    /// `alt label` block. Statements of the block are added by `body`.
+    /// `else` branches are added with `AltBuilder::else_branch`, `AltBuilder::end` adds the block
+    /// to the diagram
+    pub fn alt<F: FnOnce(Diagram) -> Diagram>(self, label: &str, body: F) -> AltBuilder {
+        AltBuilder {
+            diagram: self,
+            block: new_block(BlockKind::Alt, label, body),
+        }
+    }
+
+    /// `opt label` block
+    pub fn opt<F: FnOnce(Diagram) -> Diagram>(self, label: &str, body: F) -> Diagram {
+        self.block(BlockKind::Opt, label, body)
+    }
+
+    /// `loop label` block
+    pub fn loop_<F: FnOnce(Diagram) -> Diagram>(self, label: &str, body: F) -> Diagram {
+        self.block(BlockKind::Loop, label, body)
+    }
+
+    /// `parallel { ... }` block. Statements of the block are shown at the same time
+    pub fn parallel<F: FnOnce(Diagram) -> Diagram>(self, body: F) -> Diagram {
+        self.block(BlockKind::Parallel, "", body)
+    }
+
+    /// `option name=value`
+    pub fn option(self, name: &str, value: &str) -> Diagram {
+        self.push(StatementKind::DiagramOption {
+            name: escape_text(name),
+            value: escape_text(value),
+        })
+    }
+
+    /// `# text`. Every line of text is separate comment
+    pub fn comment(mut self, text: &str) -> Diagram {
+        for line in text.lines() {
+            self = self.push(StatementKind::Comment(format!(" {}", line)));
+        }
+        self
+    }
+
+    fn block<F: FnOnce(Diagram) -> Diagram>(self, kind: BlockKind, label: &str, body: F) -> Diagram {
+        self.push(StatementKind::Block(new_block(kind, label, body)))
+    }
+
+    fn push(mut self, kind: StatementKind) -> Diagram {
+        self.statements.push(Statement { kind, span: Span::default() });
+        self
+    }
+}
+
+/// Builder of `alt` block returned by `Diagram::alt`
+#[must_use = "`end()` should be called to add `alt` block to the diagram"]
+pub struct AltBuilder {
+    diagram: Diagram,
+    block: Block,
+}
+
+impl AltBuilder {
+    /// `else label` branch. Statements of the branch are added by `body`
+    pub fn else_branch<F: FnOnce(Diagram) -> Diagram>(mut self, label: &str, body: F) -> AltBuilder {
+        self.block.else_branches.push(ElseBranch {
+            label: escape_text(label),
+            span: Span::default(),
+            body: body(Diagram::new()).statements,
+        });
+        self
+    }
+
+    /// `end` of the block. Returns the diagram with the block added
+    pub fn end(self) -> Diagram {
+        self.diagram.push(StatementKind::Block(self.block))
+    }
+}
+
+impl Arrow {
+    pub fn new(line: LineStyle, head: ArrowHead) -> Arrow {
+        Arrow { line, head, activation: None }
+    }
+
+    /// `-->` usually used for responses
+    pub fn reply() -> Arrow {
+        Arrow::new(LineStyle::Dashed, ArrowHead::Filled)
+    }
+
+    /// The same arrow which activates receiver. E.g. `->+`
+    pub fn activating(self) -> Arrow {
+        Arrow { activation: Some(Activation::Activate), ..self }
+    }
+
+    /// The same arrow which deactivates sender. E.g. `-->-`
+    pub fn deactivating(self) -> Arrow {
+        Arrow { activation: Some(Activation::Deactivate), ..self }
+    }
+}
+
+fn new_block<F: FnOnce(Diagram) -> Diagram>(kind: BlockKind, label: &str, body: F) -> Block {
+    Block {
+        kind,
+        label: escape_text(label),
+        body: body(Diagram::new()).statements,
+        else_branches: vec![],
+    }
+}
+
+/// Name written by builder instead of empty or whitespace-only name
+pub const EMPTY_NAME: &str = "?";
+
+// Quoted names cannot contain `"`, so it is replaced with `'`
+fn name(text: &str) -> Name {
+    let text = escape_text(text).replace('"', "'");
+    Name {
+        text: if text.is_empty() { EMPTY_NAME.to_owned() } else { text },
+        span: Span::default(),
+    }
+}
+
+// Text of the statement should be on one line. Line breaks are written as `\n`
+fn escape_text(text: &str) -> String {
+    text.trim()
+        .replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse, Arrow, ArrowHead, Diagram, LineStyle, NotePosition, StatementKind};
+
+    #[test]
+    fn builder_escaping_test() {
+        let diagram = Diagram::new()
+            .title("Flow:\nfirst")
+            .participant_as("Server: \"main\"", "S")
+            .actor("note taker")
+            .message("S", "note taker", "a: b\r\nc")
+            .signal("-x", Arrow::new(LineStyle::Solid, ArrowHead::Open).activating(), "S", "")
+            .signal("S", Arrow::reply().deactivating(), "-x", "done")
+            .note_over(&["S", "note taker"], "line 1\nline 2")
+            .state_over(&["S"], "ready")
+            .note(NotePosition::LeftOf, "S", "end note")
+            .opt("x", |b| b.loop_("y", |b| b.parallel(|b| b.message("S", "S", "self"))))
+            .option("footer", "none")
+            .comment("generated\nby test")
+            .message("", " \n ", "empty names");
+        let spec = diagram.to_string();
+        assert_eq!(spec, "\
+title Flow:\\nfirst
+participant \"Server: 'main'\" as S
+actor \"note taker\"
+S->\"note taker\": a: b\\nc
+\"-x\"->>+S:
+S-->-\"-x\": done
+note over S,\"note taker\": line 1\\nline 2
+state over S: ready
+note left of S: end note
+opt x
+    loop y
+        parallel {
+            S->S: self
+        }
+    end
+end
+option footer=none
+# generated
+# by test
+?->?: empty names
+");
+        let parsed = parse(&spec).unwrap();
+        assert_eq!(parsed.to_string(), spec);
+        match parsed.statements[3].kind {
+            StatementKind::Signal(ref signal) => {
+                assert_eq!(signal.to.text, "note taker");
+                assert_eq!(signal.text, "a: b\\nc");
+            }
+            ref kind => panic!("Signal expected. Instead got: {:?}", kind),
+        }
+        assert!(parsed.check().is_empty());
+    }
+
+    #[test]
+    fn alt_builder_test() {
+        let diagram = Diagram::new()
+            .alt("x", |b| b.alt("y", |b| b).end())
+            .end()
+            .alt("z", |b| b.message("A", "B", "z"))
+            .else_branch("", |b| b)
+            .else_branch("w", |b| b.message("A", "B", "w"))
+            .end();
+        assert_eq!(diagram.to_string(), "\
+alt x
+    alt y
+    end
+end
+alt z
+    A->B: z
+else
+else w
+    A->B: w
+end
+");
+        assert!(parse(&diagram.to_string()).unwrap().check().is_empty());
+    }
+}
@@ -0,0 +1,1209 @@
+//! Parser of websequencediagrams (wsd) language.
+//!
+//! It is shared by [wsdclient](https://crates.io/crates/wsdclient) and `wsdclient-macros`,
+//! so `wsd!` macro accepts exactly the same diagrams as `wsdclient::lint`.
+//! Use it through `wsdclient::syntax`.
+
+/// Contains builder of diagram specification (methods of `Diagram`)
+pub mod builder;
+
+pub use crate::builder::AltBuilder;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+
+/// Represent position of the part of diagram specification
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    /// Byte offset of the first character
+    pub start: usize,
+
+    /// Byte offset after the last character
+    pub end: usize,
+
+    /// Line number of the first character. Starts from 1
+    pub line: usize,
+
+    /// Column of the first character in characters (not bytes). Starts from 1
+    pub column: usize,
+}
+
+/// Represent parsed diagram specification. `Display` writes it back as text
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diagram {
+    pub statements: Vec<Statement>,
+}
+
+/// Represent one statement of the diagram. Blocks (like `alt`) contain other statements
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub kind: StatementKind,
+
+    /// Lines of the statement. For multi-line notes and blocks it covers all their lines
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatementKind {
+    /// `title Some title`
+    Title(String),
+
+    /// `participant "Long name" as L` or `actor User`
+    Participant {
+        kind: ParticipantKind,
+        name: Name,
+        alias: Option<Name>,
+    },
+
+    /// `A->B: text`
+    Signal(Signal),
+
+    /// `note left of A: text`. Multi-line note is ended with `end note`, lines of its text
+    /// are separated with `\n`
+    Note {
+        position: NotePosition,
+        participants: Vec<Name>,
+        text: String,
+    },
+
+    /// `state over A,B: text`
+    State {
+        participants: Vec<Name>,
+        text: String,
+    },
+
+    /// `ref over A,B: text`. Multi-line reference is ended with `end ref` like multi-line note
+    Ref {
+        participants: Vec<Name>,
+        text: String,
+    },
+
+    /// `activate A`
+    Activate(Name),
+
+    /// `deactivate A`
+    Deactivate(Name),
+
+    /// `alt`, `opt`, `loop`, `group` block ended with `end` or `parallel {` block ended with `}`
+    Block(Block),
+
+    /// `option footer=none`
+    DiagramOption {
+        name: String,
+        value: String,
+    },
+
+    /// `# text`. Text is everything after `#`
+    Comment(String),
+
+    /// Empty line
+    Blank,
+
+    /// Statement which is not modeled by parser: `autonumber`, `destroy`, `space`, `box`,
+    /// `end box` and dividers like `==text==`. It is kept as is
+    Other(String),
+
+    /// Line which cannot be parsed. It is kept as is, see `parse_with_errors`
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParticipantKind {
+    Participant,
+    Actor,
+}
+
+/// Represent name of participant as it is written in the statement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Name {
+    /// Name without quotes
+    pub text: String,
+
+    pub span: Span,
+}
+
+/// Represent message from one participant to another
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signal {
+    pub from: Name,
+    pub arrow: Arrow,
+    pub to: Name,
+    pub text: String,
+}
+
+/// Represent arrow of the signal. E.g. `-->-` is dashed line with filled head which deactivates sender
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arrow {
+    pub line: LineStyle,
+    pub head: ArrowHead,
+    pub activation: Option<Activation>,
+}
+
+// By default arrow is `->`
+impl Default for Arrow {
+    fn default() -> Arrow {
+        Arrow {
+            line: LineStyle::Solid,
+            head: ArrowHead::Filled,
+            activation: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineStyle {
+    /// `->`
+    Solid,
+    /// `-->`
+    Dashed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrowHead {
+    /// `->`
+    Filled,
+    /// `->>`
+    Open,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activation {
+    /// `->+B` activates receiver
+    Activate,
+    /// `->-B` deactivates sender
+    Deactivate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotePosition {
+    LeftOf,
+    RightOf,
+    Over,
+}
+
+/// Represent block of statements like `alt` with its `else` branches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub kind: BlockKind,
+
+    /// Text after the keyword. Always empty for `parallel`
+    pub label: String,
+
+    pub body: Vec<Statement>,
+
+    /// Only `alt` block has `else` branches
+    pub else_branches: Vec<ElseBranch>,
+}
+
+/// Represent `else` branch of `alt` block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElseBranch {
+    pub label: String,
+
+    /// Span of `else` line
+    pub span: Span,
+
+    pub body: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockKind {
+    Alt,
+    Opt,
+    Loop,
+    Group,
+    Parallel,
+}
+
+impl BlockKind {
+    /// Keyword starting the block
+    pub fn keyword(self) -> &'static str {
+        match self {
+            BlockKind::Alt => "alt",
+            BlockKind::Opt => "opt",
+            BlockKind::Loop => "loop",
+            BlockKind::Group => "group",
+            BlockKind::Parallel => "parallel",
+        }
+    }
+
+    /// Line closing the block
+    pub fn terminator(self) -> &'static str {
+        match self {
+            BlockKind::Parallel => "}",
+            _ => "end",
+        }
+    }
+}
+
+/// Represent error in diagram specification found by parser
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.span.line, self.message)
+    }
+}
+
+impl Error for SyntaxError {}
+
+/// Parse diagram specification. Returns the first error if there are any
+pub fn parse(spec: &str) -> Result<Diagram, SyntaxError> {
+    let (diagram, errors) = parse_with_errors(spec);
+    match errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(diagram),
+    }
+}
+
+/// Parse diagram specification without stopping on errors.
+///
+/// Lines with errors are kept in the diagram as `StatementKind::Invalid`.
+/// Unclosed blocks and notes are closed at the end of specification.
+/// Errors are sorted by position.
+pub fn parse_with_errors(spec: &str) -> (Diagram, Vec<SyntaxError>) {
+    let mut parser = Parser {
+        lines: split_lines(spec),
+        position: 0,
+        errors: vec![],
+    };
+    let (statements, _) = parser.parse_body(None);
+    parser.errors.sort_by_key(|error| error.span.start);
+    (Diagram { statements }, parser.errors)
+}
+
+/// Rewrite specification into canonical layout. Specification with syntax errors is not changed,
+/// the first error is returned.
+///
+/// Layout: statements inside blocks are indented by 4 spaces, keywords are lowercase, signals
+/// are written as `A->B: text`, repeated empty lines are merged into one, empty lines at the
+/// start and the end of blocks are removed, text ends with line ending. Comments are kept.
+pub fn format(spec: &str) -> Result<String, SyntaxError> {
+    let mut diagram = parse(spec)?;
+    remove_extra_blank_lines(&mut diagram.statements);
+    Ok(diagram.to_string())
+}
+
+fn remove_extra_blank_lines(statements: &mut Vec<Statement>) {
+    let is_blank = |statement: &Statement| statement.kind == StatementKind::Blank;
+    statements.dedup_by(|next, previous| is_blank(next) && is_blank(previous));
+    while statements.first().map_or(false, is_blank) {
+        statements.remove(0);
+    }
+    while statements.last().map_or(false, is_blank) {
+        statements.pop();
+    }
+    for statement in statements {
+        if let StatementKind::Block(ref mut block) = statement.kind {
+            remove_extra_blank_lines(&mut block.body);
+            for branch in &mut block.else_branches {
+                remove_extra_blank_lines(&mut branch.body);
+            }
+        }
+    }
+}
+
+impl Diagram {
+    /// Find errors in syntactically correct diagram: deactivation of participant which is not
+    /// activated, activation without deactivation, unknown participants in notes, states and refs,
+    /// duplicate aliases. Errors are sorted by position.
+    ///
+    /// Participants are known if they are declared or used in signals anywhere in the diagram.
+    pub fn check(&self) -> Vec<SyntaxError> {
+        let mut checker = Checker::default();
+        checker.collect_participants(&self.statements);
+        checker.check_statements(&self.statements);
+        for (name, spans) in checker.activations.drain() {
+            for span in spans {
+                checker.errors.push(SyntaxError {
+                    message: format!("Activate: {} was not deactivated.", name),
+                    span,
+                });
+            }
+        }
+        checker.errors.sort_by_key(|error| error.span.start);
+        checker.errors
+    }
+}
+
+#[derive(Default)]
+struct Checker {
+    // Names used in signals and declared names (alias if it is set)
+    participants: HashSet<String>,
+    // Declared names with span of declaration
+    declarations: HashMap<String, Span>,
+    // Spans of activations which are not deactivated yet
+    activations: HashMap<String, Vec<Span>>,
+    errors: Vec<SyntaxError>,
+}
+
+impl Checker {
+    fn collect_participants(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            match statement.kind {
+                StatementKind::Participant { ref name, ref alias, .. } => {
+                    let declared = alias.as_ref().unwrap_or(name);
+                    if alias.is_some() && self.declarations.contains_key(&declared.text) {
+                        self.errors.push(SyntaxError {
+                            message: format!("Duplicate alias: {}.", declared.text),
+                            span: declared.span,
+                        });
+                    }
+                    self.declarations.entry(declared.text.clone()).or_insert(declared.span);
+                    self.participants.insert(declared.text.clone());
+                }
+                StatementKind::Signal(ref signal) => {
+                    self.participants.insert(signal.from.text.clone());
+                    self.participants.insert(signal.to.text.clone());
+                }
+                StatementKind::Block(ref block) => {
+                    self.collect_participants(&block.body);
+                    for branch in &block.else_branches {
+                        self.collect_participants(&branch.body);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn check_statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            match statement.kind {
+                StatementKind::Signal(ref signal) => match signal.arrow.activation {
+                    Some(Activation::Activate) => self.activate(&signal.to),
+                    Some(Activation::Deactivate) => self.deactivate(&signal.from),
+                    None => {}
+                },
+                StatementKind::Activate(ref name) => self.activate(name),
+                StatementKind::Deactivate(ref name) => self.deactivate(name),
+                StatementKind::Note { ref participants, .. }
+                | StatementKind::State { ref participants, .. }
+                | StatementKind::Ref { ref participants, .. } => {
+                    for name in participants {
+                        if !self.participants.contains(&name.text) {
+                            self.errors.push(SyntaxError {
+                                message: format!("Unknown participant: {}.", name.text),
+                                span: name.span,
+                            });
+                        }
+                    }
+                }
+                StatementKind::Block(ref block) => {
+                    self.check_statements(&block.body);
+                    for branch in &block.else_branches {
+                        self.check_statements(&branch.body);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn activate(&mut self, name: &Name) {
+        self.activations.entry(name.text.clone()).or_default().push(name.span);
+    }
+
+    // Message is the same as returned by API
+    fn deactivate(&mut self, name: &Name) {
+        let activated = self.activations.get_mut(&name.text).and_then(|spans| spans.pop()).is_some();
+        if !activated {
+            self.errors.push(SyntaxError {
+                message: format!("Deactivate: {} was not activated.", name.text),
+                span: name.span,
+            });
+        }
+    }
+}
+
+impl fmt::Display for Diagram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_statements(f, &self.statements, 0)
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if needs_quotes(&self.text) {
+            write!(f, "\"{}\"", self.text)
+        } else {
+            write!(f, "{}", self.text)
+        }
+    }
+}
+
+impl fmt::Display for Arrow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let line = match self.line {
+            LineStyle::Solid => "-",
+            LineStyle::Dashed => "--",
+        };
+        let head = match self.head {
+            ArrowHead::Filled => ">",
+            ArrowHead::Open => ">>",
+        };
+        let activation = match self.activation {
+            None => "",
+            Some(Activation::Activate) => "+",
+            Some(Activation::Deactivate) => "-",
+        };
+        write!(f, "{}{}{}", line, head, activation)
+    }
+}
+
+impl fmt::Display for NotePosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NotePosition::LeftOf => write!(f, "left of"),
+            NotePosition::RightOf => write!(f, "right of"),
+            NotePosition::Over => write!(f, "over"),
+        }
+    }
+}
+
+const INDENT: &str = "    ";
+
+fn write_statements(f: &mut fmt::Formatter, statements: &[Statement], depth: usize) -> fmt::Result {
+    for statement in statements {
+        write_statement(f, statement, depth)?;
+    }
+    Ok(())
+}
+
+fn write_statement(f: &mut fmt::Formatter, statement: &Statement, depth: usize) -> fmt::Result {
+    let indent = INDENT.repeat(depth);
+    match statement.kind {
+        StatementKind::Title(ref text) => writeln!(f, "{}", format!("{}title {}", indent, text).trim_end()),
+        StatementKind::Participant { kind, ref name, ref alias } => {
+            let keyword = match kind {
+                ParticipantKind::Participant => "participant",
+                ParticipantKind::Actor => "actor",
+            };
+            match alias {
+                Some(alias) => writeln!(f, "{}{} {} as {}", indent, keyword, name, alias),
+                None => writeln!(f, "{}{} {}", indent, keyword, name),
+            }
+        }
+        StatementKind::Signal(ref signal) => {
+            write!(f, "{}{}{}{}:", indent, signal.from, signal.arrow, signal.to)?;
+            write_text(f, &signal.text)
+        }
+        StatementKind::Note { position, ref participants, ref text } => {
+            write!(f, "{}note {} {}", indent, position, join_names(participants))?;
+            write_multiline_text(f, &indent, "note", text)
+        }
+        StatementKind::State { ref participants, ref text } => {
+            write!(f, "{}state over {}:", indent, join_names(participants))?;
+            write_text(f, text)
+        }
+        StatementKind::Ref { ref participants, ref text } => {
+            write!(f, "{}ref over {}", indent, join_names(participants))?;
+            write_multiline_text(f, &indent, "ref", text)
+        }
+        StatementKind::Activate(ref name) => writeln!(f, "{}activate {}", indent, name),
+        StatementKind::Deactivate(ref name) => writeln!(f, "{}deactivate {}", indent, name),
+        StatementKind::Block(ref block) => {
+            if block.kind == BlockKind::Parallel {
+                writeln!(f, "{}parallel {{", indent)?;
+            } else {
+                writeln!(f, "{}", format!("{}{} {}", indent, block.kind.keyword(), block.label).trim_end())?;
+            }
+            write_statements(f, &block.body, depth + 1)?;
+            for branch in &block.else_branches {
+                writeln!(f, "{}", format!("{}else {}", indent, branch.label).trim_end())?;
+                write_statements(f, &branch.body, depth + 1)?;
+            }
+            writeln!(f, "{}{}", indent, block.kind.terminator())
+        }
+        StatementKind::DiagramOption { ref name, ref value } => writeln!(f, "{}option {}={}", indent, name, value),
+        StatementKind::Comment(ref text) => writeln!(f, "{}#{}", indent, text),
+        StatementKind::Blank => writeln!(f),
+        StatementKind::Other(ref text) | StatementKind::Invalid(ref text) => writeln!(f, "{}{}", indent, text),
+    }
+}
+
+// Writes text of note or ref: after `:` if it has one line, otherwise on the next lines ended with
+// `end note` or `end ref`
+fn write_multiline_text(f: &mut fmt::Formatter, indent: &str, keyword: &str, text: &str) -> fmt::Result {
+    if text.contains('\n') {
+        writeln!(f)?;
+        for line in text.split('\n') {
+            writeln!(f, "{}", format!("{}{}{}", indent, INDENT, line).trim_end())?;
+        }
+        writeln!(f, "{}end {}", indent, keyword)
+    } else {
+        write!(f, ":")?;
+        write_text(f, text)
+    }
+}
+
+// Writes text after `:`. Empty text is written without trailing space
+fn write_text(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
+    if text.is_empty() {
+        writeln!(f)
+    } else {
+        writeln!(f, " {}", text)
+    }
+}
+
+fn join_names(names: &[Name]) -> String {
+    names
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+const KEYWORDS: &[&str] = &[
+    "title", "participant", "actor", "note", "state", "ref", "activate", "deactivate",
+    "alt", "else", "opt", "loop", "group", "parallel", "end", "option",
+    "autonumber", "destroy", "space", "box",
+];
+
+// Quotes are needed if name without them is parsed differently
+fn needs_quotes(name: &str) -> bool {
+    let first_word = name.split_whitespace().next().unwrap_or("").to_lowercase();
+    name.is_empty()
+        || name.trim() != name
+        || name.starts_with(&['#', '+', '-', '>'][..])
+        || name.starts_with("==")
+        || name.ends_with('-')
+        || name.contains("->")
+        || name.contains(&[':', ',', '"'][..])
+        || name.contains(" as ")
+        || (first_word != name.to_lowercase() && KEYWORDS.contains(&first_word.as_str()))
+}
+
+// Line of specification without line ending
+struct Line<'a> {
+    // Starts from 1
+    number: usize,
+    // Byte offset of the line in specification
+    start: usize,
+    text: &'a str,
+}
+
+impl<'a> Line<'a> {
+    // Span of `part` which must be a slice of the line text
+    fn span_of(&self, part: &str) -> Span {
+        let offset = part.as_ptr() as usize - self.text.as_ptr() as usize;
+        Span {
+            start: self.start + offset,
+            end: self.start + offset + part.len(),
+            line: self.number,
+            column: self.text[..offset].chars().count() + 1,
+        }
+    }
+
+    // Span of the line without surrounding whitespace
+    fn span(&self) -> Span {
+        self.span_of(self.text.trim())
+    }
+}
+
+fn split_lines(spec: &str) -> Vec<Line<'_>> {
+    let mut lines = vec![];
+    let mut start = 0;
+    for (index, line) in spec.split('\n').enumerate() {
+        lines.push(Line {
+            number: index + 1,
+            start,
+            text: if line.ends_with('\r') { &line[..line.len() - 1] } else { line },
+        });
+        start += line.len() + 1;
+    }
+    // Line ending of the last line does not start a new line
+    if spec.ends_with('\n') {
+        lines.pop();
+    }
+    lines
+}
+
+// Meaning of one line. Lines opening and closing blocks are combined into statements by parser
+enum LineItem {
+    Statement(StatementKind),
+    Open(BlockKind, String),
+    Else(String),
+    End,
+    CloseBrace,
+    // Start of multi-line note
+    Note(NotePosition, Vec<Name>),
+    // Start of multi-line ref
+    Ref(Vec<Name>),
+}
+
+// How body of the block ended
+enum Terminator {
+    Closed,
+    Else(String, Span),
+    EndOfInput,
+}
+
+struct Parser<'a> {
+    lines: Vec<Line<'a>>,
+    position: usize,
+    errors: Vec<SyntaxError>,
+}
+
+impl<'a> Parser<'a> {
+    // Parses statements until the end of input or the line closing `block`
+    fn parse_body(&mut self, block: Option<BlockKind>) -> (Vec<Statement>, Terminator) {
+        let mut statements = vec![];
+        while self.position < self.lines.len() {
+            let index = self.position;
+            self.position += 1;
+            let item = match parse_line(&self.lines[index]) {
+                Ok(item) => item,
+                Err(error) => {
+                    statements.push(self.invalid(index, error));
+                    continue;
+                }
+            };
+            let span = self.lines[index].span();
+            match item {
+                LineItem::Statement(kind) => statements.push(Statement { kind, span }),
+                LineItem::Open(kind, label) => statements.push(self.parse_block(index, kind, label)),
+                LineItem::Note(position, participants) => {
+                    let (text, span) = self.parse_multiline_text(index, "note");
+                    statements.push(Statement { kind: StatementKind::Note { position, participants, text }, span });
+                }
+                LineItem::Ref(participants) => {
+                    let (text, span) = self.parse_multiline_text(index, "ref");
+                    statements.push(Statement { kind: StatementKind::Ref { participants, text }, span });
+                }
+                LineItem::Else(label) => match block {
+                    Some(BlockKind::Alt) => return (statements, Terminator::Else(label, span)),
+                    _ => statements.push(self.invalid(index, "`else` outside of `alt` block".to_owned())),
+                },
+                LineItem::End => match block {
+                    Some(kind) if kind.terminator() == "end" => return (statements, Terminator::Closed),
+                    _ => statements.push(self.invalid(index, "`end` without open block".to_owned())),
+                },
+                LineItem::CloseBrace => match block {
+                    Some(BlockKind::Parallel) => return (statements, Terminator::Closed),
+                    _ => statements.push(self.invalid(index, "`}` without open `parallel` block".to_owned())),
+                },
+            }
+        }
+        (statements, Terminator::EndOfInput)
+    }
+
+    fn parse_block(&mut self, index: usize, kind: BlockKind, label: String) -> Statement {
+        let (body, mut terminator) = self.parse_body(Some(kind));
+        let mut else_branches = vec![];
+        while let Terminator::Else(label, span) = terminator {
+            let (body, next) = self.parse_body(Some(kind));
+            else_branches.push(ElseBranch { label, span, body });
+            terminator = next;
+        }
+        if let Terminator::EndOfInput = terminator {
+            self.errors.push(SyntaxError {
+                message: format!("`{}` block is not closed with `{}`", kind.keyword(), kind.terminator()),
+                span: self.lines[index].span(),
+            });
+        }
+        Statement {
+            kind: StatementKind::Block(Block { kind, label, body, else_branches }),
+            span: self.span_until_position(index),
+        }
+    }
+
+    // Reads text of multi-line note or ref started at the line `index` until `end note` or `end ref`
+    fn parse_multiline_text(&mut self, index: usize, keyword: &str) -> (String, Span) {
+        let mut text = vec![];
+        let mut closed = false;
+        while self.position < self.lines.len() {
+            let line = self.lines[self.position].text.trim();
+            self.position += 1;
+            if is_end_of(line, keyword) {
+                closed = true;
+                break;
+            }
+            text.push(line);
+        }
+        if !closed {
+            self.errors.push(SyntaxError {
+                message: format!("{} is not closed with `end {}`", keyword, keyword),
+                span: self.lines[index].span(),
+            });
+        }
+        (text.join("\n"), self.span_until_position(index))
+    }
+
+    // Span from the line `index` to the last consumed line
+    fn span_until_position(&self, index: usize) -> Span {
+        let first = self.lines[index].span();
+        let last = self.lines[self.position - 1].span();
+        Span { end: last.end.max(first.end), ..first }
+    }
+
+    fn invalid(&mut self, index: usize, message: String) -> Statement {
+        let line = &self.lines[index];
+        self.errors.push(SyntaxError { message, span: line.span() });
+        Statement {
+            kind: StatementKind::Invalid(line.text.trim().to_owned()),
+            span: line.span(),
+        }
+    }
+}
+
+// `end note` for keyword `note`
+fn is_end_of(line: &str, keyword: &str) -> bool {
+    let words = line.split_whitespace().collect::<Vec<&str>>();
+    words.len() == 2 && words[0].eq_ignore_ascii_case("end") && words[1].eq_ignore_ascii_case(keyword)
+}
+
+fn parse_line(line: &Line) -> Result<LineItem, String> {
+    let text = line.text.trim();
+    if text.is_empty() {
+        return Ok(LineItem::Statement(StatementKind::Blank));
+    }
+    if text.starts_with('#') {
+        return Ok(LineItem::Statement(StatementKind::Comment(text[1..].to_owned())));
+    }
+    if text == "}" {
+        return Ok(LineItem::CloseBrace);
+    }
+    // Divider
+    if text.len() >= 4 && text.starts_with("==") && text.ends_with("==") {
+        return Ok(LineItem::Statement(StatementKind::Other(text.to_owned())));
+    }
+    let (keyword, rest) = match text.find(char::is_whitespace) {
+        Some(index) => (&text[..index], text[index..].trim_start()),
+        None => (text, ""),
+    };
+    let statement = |kind| Ok(LineItem::Statement(kind));
+    match keyword.to_lowercase().as_str() {
+        "title" => statement(StatementKind::Title(rest.to_owned())),
+        "participant" => parse_participant(line, ParticipantKind::Participant, rest),
+        "actor" => parse_participant(line, ParticipantKind::Actor, rest),
+        "note" => parse_note(line, rest),
+        "state" => {
+            let rest = strip_keyword(rest, "over")
+                .ok_or_else(|| "expected `over` after `state`".to_owned())?;
+            let (participants, text) = parse_names_and_text(line, rest)?;
+            let text = text.ok_or_else(|| "expected `:` after participants".to_owned())?;
+            statement(StatementKind::State { participants, text })
+        }
+        "ref" => {
+            let rest = strip_keyword(rest, "over")
+                .ok_or_else(|| "expected `over` after `ref`".to_owned())?;
+            let (participants, text) = parse_names_and_text(line, rest)?;
+            Ok(match text {
+                Some(text) => LineItem::Statement(StatementKind::Ref { participants, text }),
+                None => LineItem::Ref(participants),
+            })
+        }
+        "activate" => statement(StatementKind::Activate(parse_single_name(line, rest)?)),
+        "deactivate" => statement(StatementKind::Deactivate(parse_single_name(line, rest)?)),
+        "alt" => Ok(LineItem::Open(BlockKind::Alt, rest.to_owned())),
+        "opt" => Ok(LineItem::Open(BlockKind::Opt, rest.to_owned())),
+        "loop" => Ok(LineItem::Open(BlockKind::Loop, rest.to_owned())),
+        "group" => Ok(LineItem::Open(BlockKind::Group, rest.to_owned())),
+        "parallel" if rest == "{" => Ok(LineItem::Open(BlockKind::Parallel, String::new())),
+        "parallel" => Err("expected `{` after `parallel`".to_owned()),
+        "else" => Ok(LineItem::Else(rest.to_owned())),
+        "end" if rest.is_empty() => Ok(LineItem::End),
+        "end" if rest.eq_ignore_ascii_case("note") => Err("`end note` without note".to_owned()),
+        "end" if rest.eq_ignore_ascii_case("ref") => Err("`end ref` without ref".to_owned()),
+        "end" if rest.eq_ignore_ascii_case("box") => statement(StatementKind::Other(text.to_owned())),
+        "autonumber" | "destroy" | "space" | "box" if find_unquoted(text, "->").is_none() => statement(StatementKind::Other(text.to_owned())),
+        "option" => {
+            let separator = rest.find('=').ok_or_else(|| "expected `name=value` after `option`".to_owned())?;
+            statement(StatementKind::DiagramOption {
+                name: rest[..separator].trim().to_owned(),
+                value: rest[separator + 1..].trim().to_owned(),
+            })
+        }
+        _ => parse_signal(line, text).map(|signal| LineItem::Statement(StatementKind::Signal(signal))),
+    }
+}
+
+fn parse_participant(line: &Line, kind: ParticipantKind, rest: &str) -> Result<LineItem, String> {
+    let (name, alias) = if rest.starts_with('"') {
+        let closing = rest[1..].find('"').ok_or_else(|| "closing `\"` is expected".to_owned())?;
+        let after = rest[closing + 2..].trim();
+        let alias = if after.is_empty() {
+            None
+        } else {
+            Some(strip_keyword(after, "as").ok_or_else(|| "expected `as` after participant name".to_owned())?)
+        };
+        (&rest[..closing + 2], alias)
+    } else {
+        match rest.rfind(" as ") {
+            Some(index) => (&rest[..index], Some(rest[index + 4..].trim())),
+            None => (rest, None),
+        }
+    };
+    let name = parse_name(line, name)?;
+    let alias = match alias {
+        Some(alias) => Some(parse_name(line, alias)?),
+        None => None,
+    };
+    Ok(LineItem::Statement(StatementKind::Participant { kind, name, alias }))
+}
+
+fn parse_note(line: &Line, rest: &str) -> Result<LineItem, String> {
+    let (position, rest) = if let Some(rest) = strip_keyword(rest, "over") {
+        (NotePosition::Over, rest)
+    } else if let Some(rest) = strip_keyword(rest, "left").and_then(|rest| strip_keyword(rest, "of")) {
+        (NotePosition::LeftOf, rest)
+    } else if let Some(rest) = strip_keyword(rest, "right").and_then(|rest| strip_keyword(rest, "of")) {
+        (NotePosition::RightOf, rest)
+    } else {
+        return Err("expected `left of`, `right of` or `over` after `note`".to_owned());
+    };
+    let (participants, text) = parse_names_and_text(line, rest)?;
+    if position != NotePosition::Over && participants.len() > 1 {
+        return Err(format!("note {} should have one participant", position));
+    }
+    Ok(match text {
+        Some(text) => LineItem::Statement(StatementKind::Note { position, participants, text }),
+        None => LineItem::Note(position, participants),
+    })
+}
+
+// `A,B: text` -> names and text. Text is None if there is no `:`
+fn parse_names_and_text(line: &Line, rest: &str) -> Result<(Vec<Name>, Option<String>), String> {
+    let (names, text) = match find_unquoted(rest, ":") {
+        Some(index) => (&rest[..index], Some(rest[index + 1..].trim().to_owned())),
+        None => (rest, None),
+    };
+    let mut participants = vec![];
+    let mut names = names;
+    loop {
+        match find_unquoted(names, ",") {
+            Some(index) => {
+                participants.push(parse_name(line, &names[..index])?);
+                names = &names[index + 1..];
+            }
+            None => {
+                participants.push(parse_name(line, names)?);
+                break;
+            }
+        }
+    }
+    Ok((participants, text))
+}
+
+fn parse_single_name(line: &Line, rest: &str) -> Result<Name, String> {
+    if find_unquoted(rest, ",").is_some() || find_unquoted(rest, ":").is_some() {
+        return Err("expected one participant".to_owned());
+    }
+    parse_name(line, rest)
+}
+
+fn parse_signal(line: &Line, text: &str) -> Result<Signal, String> {
+    let arrow_index = find_unquoted(text, "->").ok_or_else(|| "Syntax error.".to_owned())?;
+    let from = &text[..arrow_index];
+    let (from, line_style) = if from.ends_with('-') {
+        (&from[..from.len() - 1], LineStyle::Dashed)
+    } else {
+        (from, LineStyle::Solid)
+    };
+    let rest = &text[arrow_index + 2..];
+    let (rest, head) = if rest.starts_with('>') {
+        (&rest[1..], ArrowHead::Open)
+    } else {
+        (rest, ArrowHead::Filled)
+    };
+    let (rest, activation) = if rest.starts_with('+') {
+        (&rest[1..], Some(Activation::Activate))
+    } else if rest.starts_with('-') {
+        (&rest[1..], Some(Activation::Deactivate))
+    } else {
+        (rest, None)
+    };
+    let colon = find_unquoted(rest, ":").ok_or_else(|| "expected `:` after receiver of the signal".to_owned())?;
+    Ok(Signal {
+        from: parse_name(line, from)?,
+        arrow: Arrow { line: line_style, head, activation },
+        to: parse_name(line, &rest[..colon])?,
+        text: rest[colon + 1..].trim().to_owned(),
+    })
+}
+
+// `part` must be a slice of the line text. Quotes are removed
+fn parse_name(line: &Line, part: &str) -> Result<Name, String> {
+    let part = part.trim();
+    let text = if part.len() >= 2 && part.starts_with('"') && part.ends_with('"') {
+        &part[1..part.len() - 1]
+    } else if part.contains('"') {
+        return Err(format!("unexpected `\"` in participant name `{}`", part));
+    } else {
+        part
+    };
+    if text.trim().is_empty() {
+        return Err("participant name is expected".to_owned());
+    }
+    Ok(Name { text: text.to_owned(), span: line.span_of(part) })
+}
+
+// `over A` with keyword `over` -> `A`. Keyword is case insensitive and should be followed by whitespace
+fn strip_keyword<'s>(text: &'s str, keyword: &str) -> Option<&'s str> {
+    let prefix = text.get(..keyword.len())?;
+    let rest = &text[keyword.len()..];
+    if prefix.eq_ignore_ascii_case(keyword) && rest.starts_with(char::is_whitespace) {
+        Some(rest.trim_start())
+    } else {
+        None
+    }
+}
+
+// Position of `pattern` outside of quoted names
+fn find_unquoted(text: &str, pattern: &str) -> Option<usize> {
+    let mut quoted = false;
+    for (index, c) in text.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if !quoted && text[index..].starts_with(pattern) {
+            return Some(index);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sample diagrams of wsdclient in the root of the repository. They are used only by tests,
+    // so the packaged crate does not need them
+    const SAMPLE_DIAGRAMS: &[(&str, &str)] = &[
+        ("01_simple_diag.wsd", include_str!("../../sample_diagrams/01_simple_diag.wsd")),
+        ("02_errors_in_diag.wsd", include_str!("../../sample_diagrams/02_errors_in_diag.wsd")),
+        ("03_premium_features.wsd", include_str!("../../sample_diagrams/03_premium_features.wsd")),
+    ];
+
+    fn kinds(spec: &str) -> Vec<StatementKind> {
+        parse(spec).unwrap().statements.into_iter().map(|statement| statement.kind).collect()
+    }
+
+    fn name(text: &str, start: usize, line: usize, column: usize) -> Name {
+        Name { text: text.to_owned(), span: Span { start, end: start + text.len(), line, column } }
+    }
+
+    #[test]
+    fn parse_signal_test() {
+        let diagram = parse("A->B: text\n  B-->-A:\nA->>+B: a: b").unwrap();
+        assert_eq!(diagram.statements.len(), 3);
+        assert_eq!(diagram.statements[0], Statement {
+            kind: StatementKind::Signal(Signal {
+                from: name("A", 0, 1, 1),
+                arrow: Arrow::default(),
+                to: name("B", 3, 1, 4),
+                text: "text".to_owned(),
+            }),
+            span: Span { start: 0, end: 10, line: 1, column: 1 },
+        });
+        match diagram.statements[1].kind {
+            StatementKind::Signal(ref signal) => {
+                assert_eq!(signal.from, name("B", 13, 2, 3));
+                assert_eq!(signal.arrow, Arrow { line: LineStyle::Dashed, head: ArrowHead::Filled, activation: Some(Activation::Deactivate) });
+                assert_eq!(signal.text, "");
+            }
+            ref kind => panic!("Signal expected. Instead got: {:?}", kind),
+        }
+        match diagram.statements[2].kind {
+            StatementKind::Signal(ref signal) => {
+                assert_eq!(signal.arrow, Arrow { line: LineStyle::Solid, head: ArrowHead::Open, activation: Some(Activation::Activate) });
+                assert_eq!(signal.text, "a: b");
+            }
+            ref kind => panic!("Signal expected. Instead got: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn parse_statements_test() {
+        let spec = "title Auth flow\nparticipant \"Web Server\" as S\nactor User\n# comment\n\noption footer=none\nnote left of User: hi\nnote over User,S\n  line 1\n  line 2\nend note\nstate over S: ready\nactivate S\ndeactivate S";
+        let kinds = kinds(spec);
+        assert_eq!(kinds[0], StatementKind::Title("Auth flow".to_owned()));
+        match kinds[1] {
+            StatementKind::Participant { kind: ParticipantKind::Participant, ref name, alias: Some(ref alias) } => {
+                assert_eq!(name.text, "Web Server");
+                assert_eq!(name.span.column, 13);
+                assert_eq!(alias.text, "S");
+            }
+            ref kind => panic!("Participant expected. Instead got: {:?}", kind),
+        }
+        match kinds[2] {
+            StatementKind::Participant { kind: ParticipantKind::Actor, ref name, alias: None } => assert_eq!(name.text, "User"),
+            ref kind => panic!("Actor expected. Instead got: {:?}", kind),
+        }
+        assert_eq!(kinds[3], StatementKind::Comment(" comment".to_owned()));
+        assert_eq!(kinds[4], StatementKind::Blank);
+        assert_eq!(kinds[5], StatementKind::DiagramOption { name: "footer".to_owned(), value: "none".to_owned() });
+        match kinds[6] {
+            StatementKind::Note { position: NotePosition::LeftOf, ref participants, ref text } => {
+                assert_eq!(participants.len(), 1);
+                assert_eq!(text, "hi");
+            }
+            ref kind => panic!("Note expected. Instead got: {:?}", kind),
+        }
+        match kinds[7] {
+            StatementKind::Note { position: NotePosition::Over, ref participants, ref text } => {
+                assert_eq!(participants.iter().map(|name| name.text.as_str()).collect::<Vec<&str>>(), vec!["User", "S"]);
+                assert_eq!(text, "line 1\nline 2");
+            }
+            ref kind => panic!("Note expected. Instead got: {:?}", kind),
+        }
+        match kinds[8] {
+            StatementKind::State { ref participants, ref text } => {
+                assert_eq!(participants[0].text, "S");
+                assert_eq!(text, "ready");
+            }
+            ref kind => panic!("State expected. Instead got: {:?}", kind),
+        }
+        assert_eq!(kinds[9], StatementKind::Activate(name("S", 182, 13, 10)));
+        assert_eq!(kinds[10], StatementKind::Deactivate(name("S", 195, 14, 12)));
+        assert_eq!(kinds.len(), 11);
+    }
+
+    #[test]
+    fn parse_blocks_test() {
+        let spec = "alt success\n    A->B: ok\nelse failure\n    loop retry\n        A->B: again\n    end\nelse\nend\nparallel {\n    opt\n    end\n}";
+        let diagram = parse(spec).unwrap();
+        assert_eq!(diagram.statements.len(), 2);
+        match diagram.statements[0].kind {
+            StatementKind::Block(ref block) => {
+                assert_eq!(block.kind, BlockKind::Alt);
+                assert_eq!(block.label, "success");
+                assert_eq!(block.body.len(), 1);
+                assert_eq!(block.else_branches.len(), 2);
+                assert_eq!(block.else_branches[0].label, "failure");
+                assert_eq!(block.else_branches[0].span.line, 3);
+                match block.else_branches[0].body[0].kind {
+                    StatementKind::Block(ref inner) => {
+                        assert_eq!(inner.kind, BlockKind::Loop);
+                        assert_eq!(inner.body.len(), 1);
+                    }
+                    ref kind => panic!("Block expected. Instead got: {:?}", kind),
+                }
+                assert_eq!(block.else_branches[1].label, "");
+            }
+            ref kind => panic!("Block expected. Instead got: {:?}", kind),
+        }
+        assert_eq!(diagram.statements[0].span.line, 1);
+        assert_eq!(&spec[diagram.statements[1].span.start..diagram.statements[1].span.end], "parallel {\n    opt\n    end\n}");
+        assert_eq!(diagram.to_string(), format!("{}\n", spec));
+    }
+
+    #[test]
+    fn parse_other_statements_test() {
+        let spec = "autonumber 1\nparticipant A\nref over A,B: login\nref over A\n    step 1\n    step 2\nend ref\ngroup setup\n    A->B: hi\nend\n== Phase 2 ==\nspace 3\nbox Internal\nend box\ndestroy B\nspace->B: hi\n";
+        let kinds = kinds(spec);
+        assert_eq!(kinds[0], StatementKind::Other("autonumber 1".to_owned()));
+        match kinds[2] {
+            StatementKind::Ref { ref participants, ref text } => {
+                assert_eq!(participants.len(), 2);
+                assert_eq!(text, "login");
+            }
+            ref kind => panic!("Ref expected. Instead got: {:?}", kind),
+        }
+        match kinds[3] {
+            StatementKind::Ref { ref participants, ref text } => {
+                assert_eq!(participants[0].text, "A");
+                assert_eq!(text, "step 1\nstep 2");
+            }
+            ref kind => panic!("Ref expected. Instead got: {:?}", kind),
+        }
+        match kinds[4] {
+            StatementKind::Block(ref block) => {
+                assert_eq!(block.kind, BlockKind::Group);
+                assert_eq!(block.label, "setup");
+                assert_eq!(block.body.len(), 1);
+            }
+            ref kind => panic!("Block expected. Instead got: {:?}", kind),
+        }
+        assert_eq!(kinds[5], StatementKind::Other("== Phase 2 ==".to_owned()));
+        assert_eq!(kinds[6], StatementKind::Other("space 3".to_owned()));
+        assert_eq!(kinds[7], StatementKind::Other("box Internal".to_owned()));
+        assert_eq!(kinds[8], StatementKind::Other("end box".to_owned()));
+        assert_eq!(kinds[9], StatementKind::Other("destroy B".to_owned()));
+        match kinds[10] {
+            StatementKind::Signal(ref signal) => assert_eq!(signal.from.text, "space"),
+            ref kind => panic!("Signal expected. Instead got: {:?}", kind),
+        }
+        assert_eq!(kinds.len(), 11);
+        assert_eq!(format(spec).unwrap(), spec);
+        assert!(parse(spec).unwrap().check().is_empty());
+
+        let (_, errors) = parse_with_errors("end ref\nref over A");
+        assert_eq!(
+            errors.iter().map(|error| (error.span.line, error.message.as_str())).collect::<Vec<_>>(),
+            vec![(1, "`end ref` without ref"), (2, "ref is not closed with `end ref`")]
+        );
+    }
+
+    #[test]
+    fn parse_errors_test() {
+        let (diagram, errors) = parse_with_errors("A->B text\nend\nalt x\nelse\nnote over A\nB\n");
+        assert_eq!(
+            errors.iter().map(|error| (error.span.line, error.message.as_str())).collect::<Vec<_>>(),
+            vec![
+                (1, "expected `:` after receiver of the signal"),
+                (2, "`end` without open block"),
+                (3, "`alt` block is not closed with `end`"),
+                (5, "note is not closed with `end note`"),
+            ]
+        );
+        assert_eq!(diagram.statements[0].kind, StatementKind::Invalid("A->B text".to_owned()));
+        assert_eq!(errors[0].to_string(), "line 1: expected `:` after receiver of the signal");
+        assert_eq!(parse("participant A\nA text").unwrap_err().to_string(), "line 2: Syntax error.");
+        assert!(parse("parallel\n}").is_err());
+        assert!(parse("note left of A,B: text").is_err());
+        assert!(parse("activate A,B").is_err());
+    }
+
+    #[test]
+    fn check_test() {
+        let spec = "participant Alice as A\nparticipant Bob as A\nA->+B: hi\nB-->-A: ok\nB-->-A: again\nactivate C\nalt x\n    note over A,D: hi\nend\nstate over B: s";
+        let errors = parse(spec).unwrap().check();
+        assert_eq!(
+            errors.iter().map(|error| (error.span.line, error.message.as_str())).collect::<Vec<_>>(),
+            vec![
+                (2, "Duplicate alias: A."),
+                (5, "Deactivate: B was not activated."),
+                (6, "Activate: C was not deactivated."),
+                (8, "Unknown participant: D."),
+            ]
+        );
+        assert_eq!(errors[1].span.column, 1);
+        assert_eq!(errors[3].span.column, 17);
+    }
+
+    #[test]
+    fn format_test() {
+        let spec = "\n\nTitle  Flow\nA ->B :hi\n\n\n#  keep   this\nALT  yes\n\nA-->-  B:ok\n  ELSE\n\tnote over A , B\n  first\n\n    second\n  End Note\n\nend\nparallel {\n\nA->B:\n}\n\n";
+        let formatted = "title Flow\nA->B: hi\n\n#  keep   this\nalt yes\n    A-->-B: ok\nelse\n    note over A,B\n        first\n\n        second\n    end note\nend\nparallel {\n    A->B:\n}\n";
+        assert_eq!(format(spec).unwrap(), formatted);
+        assert_eq!(format(formatted).unwrap(), formatted);
+
+        let (_, simple) = SAMPLE_DIAGRAMS[0];
+        assert_eq!(format(simple).unwrap(), format!("{}\n", simple));
+        let (_, premium) = SAMPLE_DIAGRAMS[2];
+        assert_eq!(format(premium).unwrap(), premium);
+
+        assert_eq!(format("A->B: hi\nalt x").unwrap_err().span.line, 2);
+    }
+
+    #[test]
+    fn sample_diagrams_round_trip_test() {
+        for (file_name, spec) in SAMPLE_DIAGRAMS {
+            let (diagram, errors) = parse_with_errors(spec);
+            let printed = diagram.to_string();
+            let (reparsed, reparsed_errors) = parse_with_errors(&printed);
+            assert_eq!(reparsed.to_string(), printed, "{}", file_name);
+            assert_eq!(reparsed_errors.len(), errors.len(), "{}", file_name);
+            assert_eq!(reparsed.statements.len(), diagram.statements.len(), "{}", file_name);
+            if errors.is_empty() {
+                assert_eq!(printed.trim_end(), spec.trim_end(), "{}", file_name);
+            }
+        }
+    }
+}